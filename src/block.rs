@@ -0,0 +1,197 @@
+use crate::{
+    error::BlockReassemblyError,
+    packet::{CoapOption, Packet},
+};
+use heapless::Vec;
+
+/// The longest Request-Tag value allowed by RFC 9175.
+const REQUEST_TAG_MAX_SIZE: usize = 8;
+
+/// Reassembles Block1 uploads (RFC 7959) of up to S bytes, with at most T
+/// uploads in progress.
+///
+/// Uploads are told apart by their Request-Tag (RFC 9175), so interleaved
+/// uploads with different tags don't corrupt each other. A request without
+/// the option belongs to a different upload than one with an empty tag.
+#[derive(Debug, Default)]
+pub struct BlockReassembler<const S: usize, const T: usize> {
+    transfers: Vec<Transfer<S>, T>,
+}
+
+#[derive(Debug)]
+struct Transfer<const S: usize> {
+    tag: Option<Vec<u8, REQUEST_TAG_MAX_SIZE>>,
+    body: Vec<u8, S>,
+}
+
+impl<const S: usize, const T: usize> BlockReassembler<S, T> {
+    pub const fn new() -> Self {
+        BlockReassembler {
+            transfers: Vec::new(),
+        }
+    }
+
+    /// Adds the payload of a request carrying a Block1 option to its upload.
+    /// Returns the whole body once the last block is received, or None while
+    /// more blocks are expected.
+    ///
+    /// Block 0 starts the upload for its Request-Tag, replacing any upload
+    /// in progress with the same tag. Later blocks must continue an upload
+    /// with a matching tag, in order.
+    pub fn push(&mut self, packet: &Packet) -> Result<Option<Vec<u8, S>>, BlockReassemblyError> {
+        let block = packet
+            .get_block1()
+            .ok_or(BlockReassemblyError::MissingBlock1)?
+            .map_err(BlockReassemblyError::InvalidBlockValue)?;
+        let tag = match packet.get_first_option(CoapOption::RequestTag) {
+            Some(option) => Some(
+                Vec::from_slice(option.value)
+                    .map_err(|_| BlockReassemblyError::InvalidRequestTag)?,
+            ),
+            None => None,
+        };
+        let index = self.transfers.iter().position(|t| t.tag == tag);
+
+        let index = match (index, block.num) {
+            (Some(index), 0) => {
+                self.transfers[index].body.clear();
+                index
+            }
+            (None, 0) => {
+                self.transfers
+                    .push(Transfer {
+                        tag,
+                        body: Vec::new(),
+                    })
+                    .map_err(|_| BlockReassemblyError::TooManyTransfers)?;
+                self.transfers.len() - 1
+            }
+            (Some(index), _) => index,
+            (None, _) => return Err(BlockReassemblyError::UnknownTransfer),
+        };
+
+        let body = &mut self.transfers[index].body;
+        if block.num as usize * block.size() != body.len() {
+            return Err(BlockReassemblyError::UnexpectedBlock);
+        }
+        if body.extend_from_slice(packet.payload).is_err() {
+            self.transfers.swap_remove(index);
+            return Err(BlockReassemblyError::BodyTooLarge);
+        }
+        if block.more {
+            return Ok(None);
+        }
+        Ok(Some(self.transfers.swap_remove(index).body))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::packet::{BlockValue, MessageClass, MessageType, OptionPair, RequestType};
+
+    fn upload_block<'a>(tag: Option<&'a [u8]>, block: &'a [u8], payload: &'a [u8]) -> Packet<'a> {
+        let mut options = Vec::new();
+        options
+            .push(OptionPair {
+                num: CoapOption::Block1.into(),
+                value: block,
+            })
+            .unwrap();
+        if let Some(tag) = tag {
+            options
+                .push(OptionPair {
+                    num: CoapOption::RequestTag.into(),
+                    value: tag,
+                })
+                .unwrap();
+        }
+        Packet::new(
+            MessageType::Confirmable,
+            MessageClass::Request(RequestType::Put),
+            /* version= */ 1,
+            /* message_id= */ 1,
+            /* token= */ &[],
+            /* options= */ &mut options,
+            /* payload= */ payload,
+        )
+    }
+
+    #[test]
+    fn interleaved_uploads() {
+        let mut buf = [[0; 3]; 2];
+        let [first, last] = &mut buf;
+        let first = BlockValue::new(0, true, 16).unwrap().encode(first);
+        let last = BlockValue::new(1, false, 16).unwrap().encode(last);
+        let mut reassembler = BlockReassembler::<64, 2>::new();
+
+        let a = upload_block(Some(b"a"), first, b"0123456789abcdef");
+        assert_eq!(None, reassembler.push(&a).unwrap());
+        let b = upload_block(Some(b"b"), first, b"ABCDEFGHIJKLMNOP");
+        assert_eq!(None, reassembler.push(&b).unwrap());
+
+        let b = upload_block(Some(b"b"), last, b"QR");
+        assert_eq!(
+            &b"ABCDEFGHIJKLMNOPQR"[..],
+            &reassembler.push(&b).unwrap().unwrap()[..]
+        );
+        let a = upload_block(Some(b"a"), last, b"gh");
+        assert_eq!(
+            &b"0123456789abcdefgh"[..],
+            &reassembler.push(&a).unwrap().unwrap()[..]
+        );
+    }
+
+    #[test]
+    fn tag_mismatch() {
+        let mut buf = [[0; 3]; 3];
+        let [first, last, skipped] = &mut buf;
+        let first = BlockValue::new(0, true, 16).unwrap().encode(first);
+        let last = BlockValue::new(1, false, 16).unwrap().encode(last);
+        let skipped = BlockValue::new(2, false, 16).unwrap().encode(skipped);
+        let mut reassembler = BlockReassembler::<64, 2>::new();
+
+        let start = upload_block(Some(b"a"), first, b"0123456789abcdef");
+        assert_eq!(None, reassembler.push(&start).unwrap());
+        assert_eq!(
+            Err(BlockReassemblyError::UnknownTransfer),
+            reassembler.push(&upload_block(Some(b"c"), last, b"gh"))
+        );
+        assert_eq!(
+            Err(BlockReassemblyError::UnknownTransfer),
+            reassembler.push(&upload_block(None, last, b"gh"))
+        );
+        assert_eq!(
+            Err(BlockReassemblyError::UnexpectedBlock),
+            reassembler.push(&upload_block(Some(b"a"), skipped, b"gh"))
+        );
+    }
+
+    #[test]
+    fn limits() {
+        let mut buf = [0; 3];
+        let first = BlockValue::new(0, true, 16).unwrap().encode(&mut buf);
+        let mut reassembler = BlockReassembler::<8, 1>::new();
+
+        assert_eq!(
+            Err(BlockReassemblyError::BodyTooLarge),
+            reassembler.push(&upload_block(None, first, b"0123456789abcdef"))
+        );
+
+        let mut reassembler = BlockReassembler::<64, 1>::new();
+        let start = upload_block(Some(b"a"), first, b"0123456789abcdef");
+        assert_eq!(None, reassembler.push(&start).unwrap());
+        assert_eq!(
+            Err(BlockReassemblyError::TooManyTransfers),
+            reassembler.push(&upload_block(Some(b"b"), first, b"0123456789abcdef"))
+        );
+        assert_eq!(
+            Err(BlockReassemblyError::InvalidRequestTag),
+            reassembler.push(&upload_block(Some(b"012345678"), first, b""))
+        );
+        assert_eq!(
+            Err(BlockReassemblyError::MissingBlock1),
+            reassembler.push(&Packet::empty_confirmable(1))
+        );
+    }
+}
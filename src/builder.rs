@@ -0,0 +1,327 @@
+use crate::{
+    error::MessageError,
+    packet::{
+        encode_uint, CoapOption, ContentFormat, MessageClass, MessageType, OptionPair,
+        OptionValueFormat, Packet,
+    },
+    MAX_OPTIONS,
+};
+use heapless::Vec;
+
+/// Builder for outgoing packets.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PacketBuilder<'a> {
+    message_type: MessageType,
+    code: MessageClass,
+    version: u8,
+    message_id: u16,
+    token: &'a [u8],
+    options: Vec<OptionPair<'a>, MAX_OPTIONS>,
+    payload: &'a [u8],
+}
+
+impl Default for PacketBuilder<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> PacketBuilder<'a> {
+    /// Creates a builder for an empty version 1 Confirmable message.
+    pub fn new() -> Self {
+        PacketBuilder {
+            message_type: MessageType::Confirmable,
+            code: MessageClass::Empty,
+            version: 1,
+            message_id: 0,
+            token: &[],
+            options: Vec::new(),
+            payload: &[],
+        }
+    }
+
+    /// Creates a builder seeded with the fields of the packet, borrowing
+    /// its token, option values and payload.
+    pub fn from_packet(packet: &Packet<'a>) -> Self {
+        PacketBuilder {
+            message_type: packet.get_type(),
+            code: packet.get_code(),
+            version: packet.get_version(),
+            message_id: packet.get_message_id(),
+            token: packet.get_token(),
+            options: packet.options().cloned().collect(),
+            payload: packet.get_payload(),
+        }
+    }
+
+    pub fn message_type(&mut self, message_type: MessageType) -> &mut Self {
+        self.message_type = message_type;
+        self
+    }
+
+    pub fn code(&mut self, code: MessageClass) -> &mut Self {
+        self.code = code;
+        self
+    }
+
+    pub fn version(&mut self, version: u8) -> &mut Self {
+        self.version = version;
+        self
+    }
+
+    pub fn message_id(&mut self, message_id: u16) -> &mut Self {
+        self.message_id = message_id;
+        self
+    }
+
+    /// Sets the token. The length isn't checked here: `to_bytes` rejects
+    /// tokens longer than 8 bytes, which need `to_bytes_extended_token`.
+    pub fn token(&mut self, token: &'a [u8]) -> &mut Self {
+        self.token = token;
+        self
+    }
+
+    pub fn payload(&mut self, payload: &'a [u8]) -> &mut Self {
+        self.payload = payload;
+        self
+    }
+
+    /// Adds an option, after any options with the same number.
+    pub fn add_option(&mut self, option: CoapOption, value: &'a [u8]) -> Result<(), MessageError> {
+        self.options
+            .push(OptionPair {
+                num: option.into(),
+                value,
+            })
+            .map_err(|_| MessageError::OptionsLimitExceeded)
+    }
+
+    /// Adds an option holding a minimally encoded unsigned integer, using
+    /// `buf` for the encoded value. Returns IncompatibleOptionFormat if the
+    /// option isn't a uint option.
+    pub fn add_uint_option(
+        &mut self,
+        option: CoapOption,
+        value: u64,
+        buf: &'a mut [u8],
+    ) -> Result<(), MessageError> {
+        if option.value_format() != OptionValueFormat::Uint {
+            return Err(MessageError::IncompatibleOptionFormat);
+        }
+        let value = encode_uint(value, buf)?;
+        self.add_option(option, value)
+    }
+
+    /// Sets the Size1 option to the total size of a block-wise request
+    /// body (RFC 7959, section 4), encoding it into `buf`.
+    pub fn with_total_size(
+        &mut self,
+        total: u32,
+        buf: &'a mut [u8; 4],
+    ) -> Result<(), MessageError> {
+        let value = encode_uint(total.into(), buf)?;
+        let num = CoapOption::Size1.into();
+        self.options.retain(|p| p.num != num);
+        self.add_option(CoapOption::Size1, value)
+    }
+
+    /// Sets the payload together with its Content-Format option, replacing
+    /// any previous Content-Format and encoding the value into `fmt_buf`.
+    pub fn body(
+        &mut self,
+        format: ContentFormat,
+        payload: &'a [u8],
+        fmt_buf: &'a mut [u8; 2],
+    ) -> Result<(), MessageError> {
+        let value = encode_uint(usize::from(format) as u64, fmt_buf)?;
+        let num = CoapOption::ContentFormat.into();
+        self.options.retain(|p| p.num != num);
+        let index = self
+            .options
+            .iter()
+            .position(|p| p.num > num)
+            .unwrap_or(self.options.len());
+        self.options
+            .insert(index, OptionPair { num, value })
+            .map_err(|_| MessageError::OptionsLimitExceeded)?;
+        self.payload = payload;
+        Ok(())
+    }
+
+    /// Adds each segment as a Uri-Path option, in order. Segments must be
+    /// at most 255 bytes long and must not contain '/'. On error no
+    /// segment is added.
+    pub fn add_path_segments(
+        &mut self,
+        segments: impl IntoIterator<Item = &'a str>,
+    ) -> Result<(), MessageError> {
+        let len = self.options.len();
+        for segment in segments {
+            let result = if segment.len() > 255 {
+                Err(MessageError::InvalidOptionLength)
+            } else if segment.contains('/') {
+                Err(MessageError::InvalidPathSegment)
+            } else {
+                self.add_option(CoapOption::UriPath, segment.as_bytes())
+            };
+            if result.is_err() {
+                self.options.truncate(len);
+                return result;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn build(&self) -> Packet<'a> {
+        Packet::new(
+            self.message_type,
+            self.code,
+            self.version,
+            self.message_id,
+            self.token,
+            &mut self.options.clone(),
+            self.payload,
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        packet::{RequestType, ResponseType},
+        request::CoapRequest,
+    };
+
+    #[test]
+    fn path_segments() {
+        let mut builder = PacketBuilder::new();
+        builder
+            .code(MessageClass::Request(RequestType::Get))
+            .message_id(42)
+            .token(&[0x17, 0x38]);
+        builder
+            .add_path_segments(["sensors", "temp", "value"])
+            .unwrap();
+        let bytes = builder.build().to_bytes().unwrap();
+
+        let packet = Packet::from_bytes(&bytes).unwrap();
+        let request = CoapRequest::from_packet(&packet, ());
+        assert_eq!("/sensors/temp/value", request.get_path().unwrap());
+    }
+
+    #[test]
+    fn path_segment_invalid() {
+        let long = [b'a'; 256];
+        let mut builder = PacketBuilder::new();
+        assert_eq!(
+            MessageError::InvalidPathSegment,
+            builder.add_path_segments(["a", "b/c"]).unwrap_err()
+        );
+        assert_eq!(
+            MessageError::InvalidOptionLength,
+            builder
+                .add_path_segments([core::str::from_utf8(&long).unwrap()])
+                .unwrap_err()
+        );
+        assert_eq!(0, builder.build().options().len());
+    }
+
+    #[test]
+    fn path_segments_limit_exceeded() {
+        let mut builder = PacketBuilder::new();
+        builder.add_path_segments(["a"; MAX_OPTIONS - 1]).unwrap();
+        assert_eq!(
+            MessageError::OptionsLimitExceeded,
+            builder.add_path_segments(["b", "c"]).unwrap_err()
+        );
+        assert_eq!(MAX_OPTIONS - 1, builder.build().options().len());
+    }
+
+    #[test]
+    fn total_size() {
+        let mut size_buf = [0; 4];
+        let mut builder = PacketBuilder::new();
+        builder
+            .code(MessageClass::Request(RequestType::Put))
+            .payload(b"first block");
+        builder.with_total_size(3000, &mut size_buf).unwrap();
+        let bytes = builder.build().to_bytes().unwrap();
+
+        let packet = Packet::from_bytes(&bytes).unwrap();
+        assert_eq!(
+            &[0x0B, 0xB8],
+            packet.get_first_option(CoapOption::Size1).unwrap().value
+        );
+        let request = CoapRequest::from_packet(&packet, ());
+        assert_eq!(Some(3000), request.declared_size1());
+    }
+
+    #[test]
+    fn body() {
+        let mut fmt_buf = [0; 2];
+        let mut builder = PacketBuilder::new();
+        builder.code(MessageClass::Response(ResponseType::Content));
+        builder.add_option(CoapOption::ETag, &[1]).unwrap();
+        builder.add_option(CoapOption::MaxAge, &[60]).unwrap();
+        builder
+            .body(
+                ContentFormat::ApplicationJSON,
+                b"{\"temp\":21}",
+                &mut fmt_buf,
+            )
+            .unwrap();
+        assert_eq!(
+            CoapOption::ContentFormat,
+            CoapOption::from(builder.options[1].num)
+        );
+        let bytes = builder.build().to_bytes().unwrap();
+
+        let packet = Packet::from_bytes(&bytes).unwrap();
+        assert_eq!(
+            Some(ContentFormat::ApplicationJSON),
+            packet.get_content_format()
+        );
+        assert_eq!(b"{\"temp\":21}", packet.get_payload());
+    }
+
+    #[test]
+    fn uint_option() {
+        let mut max_age = [0; 8];
+        let mut path = [0; 8];
+        let mut builder = PacketBuilder::new();
+        builder.code(MessageClass::Response(ResponseType::Content));
+        assert_eq!(
+            Err(MessageError::IncompatibleOptionFormat),
+            builder.add_uint_option(CoapOption::UriPath, 1, &mut path)
+        );
+        builder
+            .add_uint_option(CoapOption::MaxAge, 3600, &mut max_age)
+            .unwrap();
+        let bytes = builder.build().to_bytes().unwrap();
+
+        let packet = Packet::from_bytes(&bytes).unwrap();
+        assert_eq!(
+            &[0x0E, 0x10],
+            packet.get_first_option(CoapOption::MaxAge).unwrap().value
+        );
+        assert_eq!(Some(Ok(3600)), packet.option_u32(CoapOption::MaxAge));
+    }
+
+    #[test]
+    fn from_packet() {
+        let buf = [
+            0x44, 0x01, 0x84, 0x9e, 0x51, 0x55, 0x77, 0xe8, 0xb2, 0x48, 0x69, 0x04, 0x54, 0x65,
+            0x73, 0x74, 0x43, 0x61, 0x3d, 0x31,
+        ];
+        let bytes = {
+            let packet = Packet::from_bytes(&buf).unwrap();
+            let mut builder = PacketBuilder::from_packet(&packet);
+            builder.message_id(0x1234);
+            builder.build().to_bytes().unwrap()
+        };
+        assert_eq!(&[0x12, 0x34], &bytes[2..4]);
+        assert_eq!(&buf[..2], &bytes[..2]);
+        assert_eq!(&buf[4..], &bytes[4..]);
+    }
+}
@@ -0,0 +1,72 @@
+use heapless::Vec;
+
+/// Remembers the last N message IDs to detect retransmitted messages.
+///
+/// IDs are compared for equality only, so the window is unaffected by the
+/// 16-bit message ID wrapping around.
+#[derive(Debug, Default)]
+pub struct MessageIdWindow<const N: usize> {
+    ids: Vec<u16, N>,
+    /// Slot overwritten by the next ID once the window is full.
+    oldest: usize,
+}
+
+impl<const N: usize> MessageIdWindow<N> {
+    pub fn new() -> Self {
+        MessageIdWindow {
+            ids: Vec::new(),
+            oldest: 0,
+        }
+    }
+
+    /// Records the message ID, returning true if it was already seen in
+    /// the last N messages.
+    pub fn seen(&mut self, message_id: u16) -> bool {
+        if self.ids.contains(&message_id) {
+            return true;
+        }
+        if N == 0 {
+            return false;
+        }
+        if self.ids.push(message_id).is_err() {
+            self.ids[self.oldest] = message_id;
+            self.oldest = (self.oldest + 1) % N;
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn duplicate() {
+        let mut window = MessageIdWindow::<4>::new();
+        assert!(!window.seen(1));
+        assert!(!window.seen(2));
+        assert!(window.seen(1));
+        assert!(window.seen(2));
+    }
+
+    #[test]
+    fn oldest_evicted() {
+        let mut window = MessageIdWindow::<4>::new();
+        for id in 0..5 {
+            assert!(!window.seen(id));
+        }
+        assert!(!window.seen(0));
+        for id in 2..5 {
+            assert!(window.seen(id));
+        }
+    }
+
+    #[test]
+    fn wraparound() {
+        let mut window = MessageIdWindow::<4>::new();
+        assert!(!window.seen(u16::MAX));
+        assert!(!window.seen(u16::MAX.wrapping_add(1)));
+        assert!(window.seen(u16::MAX));
+        assert!(window.seen(0));
+    }
+}
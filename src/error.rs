@@ -1,3 +1,4 @@
+use crate::packet::ResponseType;
 use core::{fmt, num::TryFromIntError};
 use heapless::String;
 
@@ -11,6 +12,46 @@ pub enum MessageError {
     InvalidOptionLength,
     OptionsLimitExceeded,
     PathLengthExceeded,
+    NotAResponse,
+    InvalidPathSegment,
+    InvalidUtf8,
+    BufferTooSmall,
+    ProxyUriWithUriOptions,
+    ProxySchemeWithoutUriHost,
+    IncompatibleOptionFormat,
+    DuplicateOption,
+    InvalidUri,
+    NonMinimalUint,
+    UnknownCriticalOption,
+    InvalidPercentEncoding,
+}
+
+impl MessageError {
+    /// Returns the response a server should send for a request that failed
+    /// with this error, or None if the message should be silently dropped.
+    pub fn to_response_code(&self) -> Option<ResponseType> {
+        match self {
+            MessageError::InvalidHeader
+            | MessageError::InvalidPacketLength
+            | MessageError::InvalidTokenLength
+            | MessageError::NotAResponse => None,
+            MessageError::InvalidOptionDelta
+            | MessageError::InvalidOptionLength
+            | MessageError::OptionsLimitExceeded
+            | MessageError::InvalidPathSegment
+            | MessageError::InvalidUtf8
+            | MessageError::ProxyUriWithUriOptions
+            | MessageError::ProxySchemeWithoutUriHost
+            | MessageError::IncompatibleOptionFormat
+            | MessageError::DuplicateOption
+            | MessageError::NonMinimalUint
+            | MessageError::UnknownCriticalOption => Some(ResponseType::BadOption),
+            MessageError::PathLengthExceeded
+            | MessageError::InvalidUri
+            | MessageError::InvalidPercentEncoding => Some(ResponseType::BadRequest),
+            MessageError::BufferTooSmall => Some(ResponseType::InternalServerError),
+        }
+    }
 }
 
 impl fmt::Display for MessageError {
@@ -40,10 +81,60 @@ impl fmt::Display for MessageError {
             MessageError::PathLengthExceeded => {
                 write!(f, "CoAP error: path length exceeded")
             }
+            MessageError::NotAResponse => {
+                write!(f, "CoAP error: message code is not a response code")
+            }
+            MessageError::InvalidPathSegment => {
+                write!(f, "CoAP error: invalid path segment")
+            }
+            MessageError::InvalidUtf8 => {
+                write!(f, "CoAP error: option value is not valid UTF-8")
+            }
+            MessageError::BufferTooSmall => {
+                write!(f, "CoAP error: output buffer too small")
+            }
+            MessageError::ProxyUriWithUriOptions => {
+                write!(f, "CoAP error: Proxy-Uri combined with Uri-* options")
+            }
+            MessageError::ProxySchemeWithoutUriHost => {
+                write!(f, "CoAP error: Proxy-Scheme without Uri-Host")
+            }
+            MessageError::IncompatibleOptionFormat => {
+                write!(f, "CoAP error: value doesn't match the option format")
+            }
+            MessageError::DuplicateOption => {
+                write!(f, "CoAP error: non-repeatable option occurs more than once")
+            }
+            MessageError::InvalidUri => {
+                write!(f, "CoAP error: invalid coap URI")
+            }
+            MessageError::NonMinimalUint => {
+                write!(f, "CoAP error: uint option value has leading zero bytes")
+            }
+            MessageError::UnknownCriticalOption => {
+                write!(f, "CoAP error: unrecognized critical option")
+            }
+            MessageError::InvalidPercentEncoding => {
+                write!(f, "CoAP error: malformed percent-encoding")
+            }
         }
     }
 }
 
+/// A decoding error together with the offset of the byte where decoding
+/// failed.
+#[derive(Debug, PartialEq)]
+pub struct DetailedMessageError {
+    pub kind: MessageError,
+    pub offset: usize,
+}
+
+impl fmt::Display for DetailedMessageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at byte {}", self.kind, self.offset)
+    }
+}
+
 /// The error that can occur when parsing a content-format.
 #[derive(Debug, PartialEq)]
 pub struct InvalidContentFormat;
@@ -95,3 +186,92 @@ impl fmt::Display for InvalidBlockValue {
         }
     }
 }
+
+/// The errors that can occur when reassembling a Block1 upload.
+#[derive(Debug, PartialEq)]
+pub enum BlockReassemblyError {
+    /// The request has no Block1 option.
+    MissingBlock1,
+    InvalidBlockValue(InvalidBlockValue),
+    /// The Request-Tag is longer than 8 bytes.
+    InvalidRequestTag,
+    /// No upload with the request's Request-Tag is in progress.
+    UnknownTransfer,
+    /// The block doesn't follow the blocks received so far.
+    UnexpectedBlock,
+    BodyTooLarge,
+    TooManyTransfers,
+}
+
+impl fmt::Display for BlockReassemblyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BlockReassemblyError::MissingBlock1 => write!(f, "missing Block1 option"),
+            BlockReassemblyError::InvalidBlockValue(err) => write!(f, "{}", err),
+            BlockReassemblyError::InvalidRequestTag => write!(f, "invalid Request-Tag"),
+            BlockReassemblyError::UnknownTransfer => {
+                write!(f, "no upload in progress for the Request-Tag")
+            }
+            BlockReassemblyError::UnexpectedBlock => write!(f, "unexpected block number"),
+            BlockReassemblyError::BodyTooLarge => write!(f, "reassembled body too large"),
+            BlockReassemblyError::TooManyTransfers => write!(f, "too many uploads in progress"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for MessageError {}
+
+#[cfg(feature = "std")]
+impl std::error::Error for DetailedMessageError {}
+
+#[cfg(feature = "std")]
+impl std::error::Error for InvalidContentFormat {}
+
+#[cfg(feature = "std")]
+impl std::error::Error for InvalidObserve {}
+
+#[cfg(feature = "std")]
+impl std::error::Error for IncompatibleOptionValueFormat {}
+
+#[cfg(feature = "std")]
+impl std::error::Error for InvalidBlockValue {}
+
+#[cfg(feature = "std")]
+impl std::error::Error for BlockReassemblyError {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn to_response_code() {
+        assert_eq!(
+            Some(ResponseType::BadOption),
+            MessageError::InvalidOptionLength.to_response_code()
+        );
+        assert_eq!(
+            Some(ResponseType::BadOption),
+            MessageError::InvalidOptionDelta.to_response_code()
+        );
+        assert_eq!(
+            Some(ResponseType::BadOption),
+            MessageError::OptionsLimitExceeded.to_response_code()
+        );
+        assert_eq!(
+            Some(ResponseType::BadRequest),
+            MessageError::InvalidPercentEncoding.to_response_code()
+        );
+        assert_eq!(None, MessageError::InvalidPacketLength.to_response_code());
+        assert_eq!(None, MessageError::InvalidHeader.to_response_code());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn std_error() {
+        use std::{boxed::Box, error::Error, string::ToString};
+
+        let err: Box<dyn Error> = Box::new(MessageError::InvalidHeader);
+        assert_eq!("CoAP error: invalid header", err.to_string());
+    }
+}
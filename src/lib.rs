@@ -1,10 +1,19 @@
 #![no_std]
 
+#[cfg(feature = "std")]
+extern crate std;
+
+pub mod block;
+pub mod builder;
+pub mod dedup;
 pub mod error;
 pub mod packet;
 pub mod request;
 pub mod response;
+pub mod tcp;
 
 pub const PACKET_MAX_SIZE: usize = 4096;
 pub const MAX_OPTIONS: usize = 32;
 pub const PATH_MAX_SIZE: usize = 128;
+/// The longest token the RFC 8974 extended token length can encode.
+pub const EXTENDED_TOKEN_MAX_SIZE: usize = 65804;
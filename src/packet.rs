@@ -1,7 +1,8 @@
 use crate::error::{
-    IncompatibleOptionValueFormat, InvalidContentFormat, InvalidObserve, MessageError,
+    DetailedMessageError, IncompatibleOptionValueFormat, InvalidBlockValue, InvalidContentFormat,
+    InvalidObserve, MessageError,
 };
-use crate::{MAX_OPTIONS, PACKET_MAX_SIZE};
+use crate::{builder::PacketBuilder, EXTENDED_TOKEN_MAX_SIZE, MAX_OPTIONS, PACKET_MAX_SIZE};
 use core::{convert::TryFrom, fmt::Write};
 use heapless::{String, Vec};
 
@@ -13,113 +14,291 @@ macro_rules! u8_to_unsigned_be {
     })
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+/// Generates a table of the numbers assigned to the variants of an enum,
+/// together with the lookup from a number and the conversion back to the
+/// number, so that both directions come from a single list. Repeated
+/// numbers or variants fail to compile. The conversion to the number must
+/// also cover the variants without a table entry, given as extra arms.
+macro_rules! number_table {
+    (
+        $(#[$meta:meta])*
+        $vis:vis $table:ident: $num:ty => $enum:ident {
+            $($number:literal => $variant:ident $(($inner:ident::$inner_variant:ident))?,)*
+        }
+        $($extra_pat:pat => $extra_expr:expr,)*
+    ) => {
+        $(#[$meta])*
+        $vis const $table: &[($num, $enum)] = &[
+            $(($number, $enum::$variant $(($inner::$inner_variant))?),)*
+        ];
+
+        impl $enum {
+            /// Returns the variant the number is assigned to in the table.
+            #[deny(unreachable_patterns)]
+            fn from_number(number: $num) -> Option<$enum> {
+                match number {
+                    $($number => Some($enum::$variant $(($inner::$inner_variant))?),)*
+                    _ => None,
+                }
+            }
+        }
+
+        impl From<$enum> for $num {
+            #[deny(unreachable_patterns)]
+            fn from(value: $enum) -> $num {
+                match value {
+                    $($enum::$variant $(($inner::$inner_variant))? => $number,)*
+                    $($extra_pat => $extra_expr,)*
+                }
+            }
+        }
+    };
+}
+
+/// Encodes the value as a minimal length unsigned integer option value
+/// (RFC 7252, section 3.2), returning the used part of `buf`.
+pub fn encode_uint(value: u64, buf: &mut [u8]) -> Result<&[u8], MessageError> {
+    let bytes = value.to_be_bytes();
+    let len = 8 - value.leading_zeros() as usize / 8;
+    let out = buf.get_mut(..len).ok_or(MessageError::BufferTooSmall)?;
+    out.copy_from_slice(&bytes[8 - len..]);
+    Ok(out)
+}
+
+/// Encodes the value as a minimal length two's complement integer,
+/// returning the used part of `buf`. Signed values are non-standard and
+/// only meant for vendor options, see `Packet::option_i32`.
+pub fn encode_int(value: i32, buf: &mut [u8]) -> Result<&[u8], MessageError> {
+    let bytes = value.to_be_bytes();
+    let redundant = if value < 0 {
+        value.leading_ones()
+    } else {
+        value.leading_zeros()
+    };
+    // Keep the sign bit in the first byte, and encode 0 as an empty value.
+    let len = if value == 0 {
+        0
+    } else {
+        4 - (redundant as usize - 1) / 8
+    };
+    let out = buf.get_mut(..len).ok_or(MessageError::BufferTooSmall)?;
+    out.copy_from_slice(&bytes[4 - len..]);
+    Ok(out)
+}
+
+/// Appends the Uri-Host, Uri-Port, Uri-Path and Uri-Query options for a
+/// `coap` or `coaps` URI to `out` (RFC 7252, section 6.4). Uri-Host is only
+/// added for a host that isn't an IP literal, and Uri-Port only for a
+/// non-default port, and Uri-Query only for a non-empty query. On error
+/// `out` is left as it was.
+///
+/// The options borrow their values, and two kinds of value don't appear in
+/// `uri` as bytes: the Uri-Port value is the binary port number, encoded
+/// into `port_buf`, and percent-encoded components are decoded into
+/// `decode_buf`. The other components borrow from `uri`.
+pub fn parse_coap_uri<'a>(
+    uri: &'a str,
+    port_buf: &'a mut [u8; 2],
+    decode_buf: &'a mut [u8],
+    out: &mut Vec<OptionPair<'a>, MAX_OPTIONS>,
+) -> Result<(), MessageError> {
+    let len = out.len();
+    let result = push_uri_options(uri, port_buf, decode_buf, out);
+    if result.is_err() {
+        out.truncate(len);
+    }
+    result
+}
+
+fn push_uri_options<'a>(
+    uri: &'a str,
+    port_buf: &'a mut [u8; 2],
+    mut decode_buf: &'a mut [u8],
+    out: &mut Vec<OptionPair<'a>, MAX_OPTIONS>,
+) -> Result<(), MessageError> {
+    let (scheme, rest) = uri.split_once("://").ok_or(MessageError::InvalidUri)?;
+    let default_port = if scheme.eq_ignore_ascii_case("coap") {
+        5683
+    } else if scheme.eq_ignore_ascii_case("coaps") {
+        5684
+    } else {
+        return Err(MessageError::InvalidUri);
+    };
+    if rest.contains('#') {
+        return Err(MessageError::InvalidUri);
+    }
+
+    let authority_end = rest.find(['/', '?']).unwrap_or(rest.len());
+    let (authority, rest) = rest.split_at(authority_end);
+    let port_start = match authority.rfind(']') {
+        Some(end) => authority[end..].find(':').map(|i| end + i),
+        None => authority.find(':'),
+    };
+    let (host, port) = match port_start {
+        Some(i) => (&authority[..i], &authority[i + 1..]),
+        None => (authority, ""),
+    };
+    if host.is_empty() {
+        return Err(MessageError::InvalidUri);
+    }
+    let port = if port.is_empty() {
+        default_port
+    } else {
+        port.parse::<u16>().map_err(|_| MessageError::InvalidUri)?
+    };
+    let (path, query) = match rest.split_once('?') {
+        Some((path, query)) => (path, Some(query).filter(|query| !query.is_empty())),
+        None => (rest, None),
+    };
+
+    let mut push = |option: CoapOption, value: &'a [u8]| {
+        out.push(OptionPair {
+            num: option.into(),
+            value,
+        })
+        .map_err(|_| MessageError::OptionsLimitExceeded)
+    };
+    let ip_literal = host.starts_with('[') || host.parse::<core::net::Ipv4Addr>().is_ok();
+    if !ip_literal {
+        push(CoapOption::UriHost, percent_decode(host, &mut decode_buf)?)?;
+    }
+    if port != default_port {
+        push(CoapOption::UriPort, encode_uint(port.into(), port_buf)?)?;
+    }
+    if let Some(path) = path.strip_prefix('/').filter(|path| !path.is_empty()) {
+        for segment in path.split('/') {
+            push(
+                CoapOption::UriPath,
+                percent_decode(segment, &mut decode_buf)?,
+            )?;
+        }
+    }
+    for argument in query.into_iter().flat_map(|query| query.split('&')) {
+        push(
+            CoapOption::UriQuery,
+            percent_decode(argument, &mut decode_buf)?,
+        )?;
+    }
+    Ok(())
+}
+
+/// Percent-decodes the URI component into the front of `buf`, which is
+/// advanced past the decoded bytes. Components without a `%` are returned
+/// as they are.
+fn percent_decode<'a>(
+    component: &'a str,
+    buf: &mut &'a mut [u8],
+) -> Result<&'a [u8], MessageError> {
+    if !component.contains('%') {
+        return Ok(component.as_bytes());
+    }
+    let mut len = 0;
+    let mut bytes = component.bytes();
+    while let Some(b) = bytes.next() {
+        let b = if b == b'%' {
+            let mut hex_digit = || {
+                bytes
+                    .next()
+                    .and_then(|d| (d as char).to_digit(16))
+                    .ok_or(MessageError::InvalidUri)
+            };
+            (hex_digit()? << 4 | hex_digit()?) as u8
+        } else {
+            b
+        };
+        *buf.get_mut(len).ok_or(MessageError::BufferTooSmall)? = b;
+        len += 1;
+    }
+    let (decoded, rest) = core::mem::take(buf).split_at_mut(len);
+    *buf = rest;
+    Ok(decoded)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum MessageClass {
     Empty,
     Request(RequestType),
     Response(ResponseType),
+    /// The 7.01 to 7.05 codes of RFC 8323, only valid over reliable
+    /// transports. They are decoded as Signaling regardless of the
+    /// transport, so over UDP they no longer show up as Reserved.
+    Signaling(SignalType),
     Reserved(u8),
 }
 
+number_table! {
+    /// The numbers of the known message codes.
+    pub MESSAGE_CLASS_TABLE: u8 => MessageClass {
+        0x00 => Empty,
+
+        0x01 => Request(RequestType::Get),
+        0x02 => Request(RequestType::Post),
+        0x03 => Request(RequestType::Put),
+        0x04 => Request(RequestType::Delete),
+        0x05 => Request(RequestType::Fetch),
+        0x06 => Request(RequestType::Patch),
+        0x07 => Request(RequestType::IPatch),
+
+        0x41 => Response(ResponseType::Created),
+        0x42 => Response(ResponseType::Deleted),
+        0x43 => Response(ResponseType::Valid),
+        0x44 => Response(ResponseType::Changed),
+        0x45 => Response(ResponseType::Content),
+        0x5F => Response(ResponseType::Continue),
+
+        0x80 => Response(ResponseType::BadRequest),
+        0x81 => Response(ResponseType::Unauthorized),
+        0x82 => Response(ResponseType::BadOption),
+        0x83 => Response(ResponseType::Forbidden),
+        0x84 => Response(ResponseType::NotFound),
+        0x85 => Response(ResponseType::MethodNotAllowed),
+        0x86 => Response(ResponseType::NotAcceptable),
+        0x89 => Response(ResponseType::Conflict),
+        0x8C => Response(ResponseType::PreconditionFailed),
+        0x8D => Response(ResponseType::RequestEntityTooLarge),
+        0x8F => Response(ResponseType::UnsupportedContentFormat),
+        0x88 => Response(ResponseType::RequestEntityIncomplete),
+        0x96 => Response(ResponseType::UnprocessableEntity),
+        0x9d => Response(ResponseType::TooManyRequests),
+
+        0xA0 => Response(ResponseType::InternalServerError),
+        0xA1 => Response(ResponseType::NotImplemented),
+        0xA2 => Response(ResponseType::BadGateway),
+        0xA3 => Response(ResponseType::ServiceUnavailable),
+        0xA4 => Response(ResponseType::GatewayTimeout),
+        0xA5 => Response(ResponseType::ProxyingNotSupported),
+        0xA8 => Response(ResponseType::HopLimitReached),
+
+        0xE1 => Signaling(SignalType::Csm),
+        0xE2 => Signaling(SignalType::Ping),
+        0xE3 => Signaling(SignalType::Pong),
+        0xE4 => Signaling(SignalType::Release),
+        0xE5 => Signaling(SignalType::Abort),
+    }
+    MessageClass::Request(RequestType::UnKnown) => 0xFF,
+    MessageClass::Response(ResponseType::UnKnown) => 0xFF,
+    MessageClass::Reserved(c) => c,
+}
+
 impl From<u8> for MessageClass {
     fn from(number: u8) -> MessageClass {
-        match number {
-            0x00 => MessageClass::Empty,
-
-            0x01 => MessageClass::Request(RequestType::Get),
-            0x02 => MessageClass::Request(RequestType::Post),
-            0x03 => MessageClass::Request(RequestType::Put),
-            0x04 => MessageClass::Request(RequestType::Delete),
-            0x05 => MessageClass::Request(RequestType::Fetch),
-            0x06 => MessageClass::Request(RequestType::Patch),
-            0x07 => MessageClass::Request(RequestType::IPatch),
-
-            0x41 => MessageClass::Response(ResponseType::Created),
-            0x42 => MessageClass::Response(ResponseType::Deleted),
-            0x43 => MessageClass::Response(ResponseType::Valid),
-            0x44 => MessageClass::Response(ResponseType::Changed),
-            0x45 => MessageClass::Response(ResponseType::Content),
-            0x5F => MessageClass::Response(ResponseType::Continue),
-
-            0x80 => MessageClass::Response(ResponseType::BadRequest),
-            0x81 => MessageClass::Response(ResponseType::Unauthorized),
-            0x82 => MessageClass::Response(ResponseType::BadOption),
-            0x83 => MessageClass::Response(ResponseType::Forbidden),
-            0x84 => MessageClass::Response(ResponseType::NotFound),
-            0x85 => MessageClass::Response(ResponseType::MethodNotAllowed),
-            0x86 => MessageClass::Response(ResponseType::NotAcceptable),
-            0x89 => MessageClass::Response(ResponseType::Conflict),
-            0x8C => MessageClass::Response(ResponseType::PreconditionFailed),
-            0x8D => MessageClass::Response(ResponseType::RequestEntityTooLarge),
-            0x8F => MessageClass::Response(ResponseType::UnsupportedContentFormat),
-            0x88 => MessageClass::Response(ResponseType::RequestEntityIncomplete),
-            0x96 => MessageClass::Response(ResponseType::UnprocessableEntity),
-            0x9d => MessageClass::Response(ResponseType::TooManyRequests),
-
-            0xA0 => MessageClass::Response(ResponseType::InternalServerError),
-            0xA1 => MessageClass::Response(ResponseType::NotImplemented),
-            0xA2 => MessageClass::Response(ResponseType::BadGateway),
-            0xA3 => MessageClass::Response(ResponseType::ServiceUnavailable),
-            0xA4 => MessageClass::Response(ResponseType::GatewayTimeout),
-            0xA5 => MessageClass::Response(ResponseType::ProxyingNotSupported),
-            0xA8 => MessageClass::Response(ResponseType::HopLimitReached),
-
-            n => MessageClass::Reserved(n),
-        }
+        MessageClass::from_number(number).unwrap_or(MessageClass::Reserved(number))
     }
 }
 
-impl From<MessageClass> for u8 {
-    fn from(class: MessageClass) -> u8 {
-        match class {
-            MessageClass::Empty => 0x00,
-
-            MessageClass::Request(RequestType::Get) => 0x01,
-            MessageClass::Request(RequestType::Post) => 0x02,
-            MessageClass::Request(RequestType::Put) => 0x03,
-            MessageClass::Request(RequestType::Delete) => 0x04,
-            MessageClass::Request(RequestType::Fetch) => 0x05,
-            MessageClass::Request(RequestType::Patch) => 0x06,
-            MessageClass::Request(RequestType::IPatch) => 0x07,
-            MessageClass::Request(RequestType::UnKnown) => 0xFF,
-
-            MessageClass::Response(ResponseType::Created) => 0x41,
-            MessageClass::Response(ResponseType::Deleted) => 0x42,
-            MessageClass::Response(ResponseType::Valid) => 0x43,
-            MessageClass::Response(ResponseType::Changed) => 0x44,
-            MessageClass::Response(ResponseType::Content) => 0x45,
-            MessageClass::Response(ResponseType::Continue) => 0x5F,
-
-            MessageClass::Response(ResponseType::BadRequest) => 0x80,
-            MessageClass::Response(ResponseType::Unauthorized) => 0x81,
-            MessageClass::Response(ResponseType::BadOption) => 0x82,
-            MessageClass::Response(ResponseType::Forbidden) => 0x83,
-            MessageClass::Response(ResponseType::NotFound) => 0x84,
-            MessageClass::Response(ResponseType::MethodNotAllowed) => 0x85,
-            MessageClass::Response(ResponseType::NotAcceptable) => 0x86,
-            MessageClass::Response(ResponseType::Conflict) => 0x89,
-            MessageClass::Response(ResponseType::PreconditionFailed) => 0x8C,
-            MessageClass::Response(ResponseType::RequestEntityTooLarge) => 0x8D,
-            MessageClass::Response(ResponseType::UnsupportedContentFormat) => 0x8F,
-            MessageClass::Response(ResponseType::RequestEntityIncomplete) => 0x88,
-            MessageClass::Response(ResponseType::UnprocessableEntity) => 0x96,
-            MessageClass::Response(ResponseType::TooManyRequests) => 0x9d,
-
-            MessageClass::Response(ResponseType::InternalServerError) => 0xA0,
-            MessageClass::Response(ResponseType::NotImplemented) => 0xA1,
-            MessageClass::Response(ResponseType::BadGateway) => 0xA2,
-            MessageClass::Response(ResponseType::ServiceUnavailable) => 0xA3,
-            MessageClass::Response(ResponseType::GatewayTimeout) => 0xA4,
-            MessageClass::Response(ResponseType::ProxyingNotSupported) => 0xA5,
-            MessageClass::Response(ResponseType::HopLimitReached) => 0xA8,
-            MessageClass::Response(ResponseType::UnKnown) => 0xFF,
-
-            MessageClass::Reserved(c) => c,
-        }
+impl From<RequestType> for MessageClass {
+    fn from(method: RequestType) -> MessageClass {
+        MessageClass::Request(method)
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+impl From<ResponseType> for MessageClass {
+    fn from(status: ResponseType) -> MessageClass {
+        MessageClass::Response(status)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum RequestType {
     Get,
     Post,
@@ -131,7 +310,7 @@ pub enum RequestType {
     UnKnown,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum ResponseType {
     // 200 Codes
     Created,
@@ -169,8 +348,66 @@ pub enum ResponseType {
     UnKnown,
 }
 
+impl ResponseType {
+    /// Returns the 2.xx response codes.
+    pub fn success_codes() -> &'static [ResponseType] {
+        &[
+            ResponseType::Created,
+            ResponseType::Deleted,
+            ResponseType::Valid,
+            ResponseType::Changed,
+            ResponseType::Content,
+            ResponseType::Continue,
+        ]
+    }
+
+    /// Returns the 4.xx response codes.
+    pub fn client_error_codes() -> &'static [ResponseType] {
+        &[
+            ResponseType::BadRequest,
+            ResponseType::Unauthorized,
+            ResponseType::BadOption,
+            ResponseType::Forbidden,
+            ResponseType::NotFound,
+            ResponseType::MethodNotAllowed,
+            ResponseType::NotAcceptable,
+            ResponseType::Conflict,
+            ResponseType::PreconditionFailed,
+            ResponseType::RequestEntityTooLarge,
+            ResponseType::UnsupportedContentFormat,
+            ResponseType::RequestEntityIncomplete,
+            ResponseType::UnprocessableEntity,
+            ResponseType::TooManyRequests,
+        ]
+    }
+
+    /// Returns the 5.xx response codes.
+    pub fn server_error_codes() -> &'static [ResponseType] {
+        &[
+            ResponseType::InternalServerError,
+            ResponseType::NotImplemented,
+            ResponseType::BadGateway,
+            ResponseType::ServiceUnavailable,
+            ResponseType::GatewayTimeout,
+            ResponseType::ProxyingNotSupported,
+            ResponseType::HopLimitReached,
+        ]
+    }
+}
+
+/// The signaling codes of CoAP over reliable transports (RFC 8323,
+/// section 5).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SignalType {
+    Csm,
+    Ping,
+    Pong,
+    Release,
+    Abort,
+}
+
 /// CoAP request/response message type.
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum MessageType {
     Confirmable,
     NonConfirmable,
@@ -178,8 +415,17 @@ pub enum MessageType {
     Reset,
 }
 
+/// How a received message is matched to a pending exchange (RFC 7252,
+/// section 5.3.2).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CorrelationMode {
+    ByMessageId,
+    ByToken,
+    Both,
+}
+
 /// The CoAP options.
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum CoapOption {
     IfMatch,
     UriHost,
@@ -202,69 +448,138 @@ pub enum CoapOption {
     Size1,
     Size2,
     NoResponse,
+    RequestTag,
     Unknown(u16),
 }
 
+number_table! {
+    /// The numbers of the known options.
+    pub OPTION_TABLE: u16 => CoapOption {
+        1 => IfMatch,
+        3 => UriHost,
+        4 => ETag,
+        5 => IfNoneMatch,
+        6 => Observe,
+        7 => UriPort,
+        8 => LocationPath,
+        9 => Oscore,
+        11 => UriPath,
+        12 => ContentFormat,
+        14 => MaxAge,
+        15 => UriQuery,
+        17 => Accept,
+        20 => LocationQuery,
+        23 => Block2,
+        27 => Block1,
+        28 => Size2,
+        35 => ProxyUri,
+        39 => ProxyScheme,
+        60 => Size1,
+        258 => NoResponse,
+        292 => RequestTag,
+    }
+    CoapOption::Unknown(number) => number,
+}
+
 impl From<u16> for CoapOption {
     fn from(number: u16) -> CoapOption {
-        match number {
-            1 => CoapOption::IfMatch,
-            3 => CoapOption::UriHost,
-            4 => CoapOption::ETag,
-            5 => CoapOption::IfNoneMatch,
-            6 => CoapOption::Observe,
-            7 => CoapOption::UriPort,
-            8 => CoapOption::LocationPath,
-            9 => CoapOption::Oscore,
-            11 => CoapOption::UriPath,
-            12 => CoapOption::ContentFormat,
-            14 => CoapOption::MaxAge,
-            15 => CoapOption::UriQuery,
-            17 => CoapOption::Accept,
-            20 => CoapOption::LocationQuery,
-            23 => CoapOption::Block2,
-            27 => CoapOption::Block1,
-            35 => CoapOption::ProxyUri,
-            39 => CoapOption::ProxyScheme,
-            60 => CoapOption::Size1,
-            28 => CoapOption::Size2,
-            258 => CoapOption::NoResponse,
-            _ => CoapOption::Unknown(number),
-        }
+        CoapOption::from_number(number).unwrap_or(CoapOption::Unknown(number))
     }
 }
 
-impl From<CoapOption> for u16 {
-    fn from(option: CoapOption) -> u16 {
-        match option {
-            CoapOption::IfMatch => 1,
-            CoapOption::UriHost => 3,
-            CoapOption::ETag => 4,
-            CoapOption::IfNoneMatch => 5,
-            CoapOption::Observe => 6,
-            CoapOption::UriPort => 7,
-            CoapOption::LocationPath => 8,
-            CoapOption::Oscore => 9,
-            CoapOption::UriPath => 11,
-            CoapOption::ContentFormat => 12,
-            CoapOption::MaxAge => 14,
-            CoapOption::UriQuery => 15,
-            CoapOption::Accept => 17,
-            CoapOption::LocationQuery => 20,
-            CoapOption::Block2 => 23,
-            CoapOption::Block1 => 27,
-            CoapOption::ProxyUri => 35,
-            CoapOption::ProxyScheme => 39,
-            CoapOption::Size1 => 60,
-            CoapOption::Size2 => 28,
-            CoapOption::NoResponse => 258,
-            CoapOption::Unknown(number) => number,
+/// The canonical option value formats (RFC 7252, section 3.2).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OptionValueFormat {
+    Empty,
+    Opaque,
+    Uint,
+    String,
+}
+
+/// An option value decoded according to its value format. Values that
+/// don't match the format are kept as opaque bytes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DecodedValue<'a> {
+    Empty,
+    Opaque(&'a [u8]),
+    Uint(u64),
+    Str(&'a str),
+}
+
+impl CoapOption {
+    /// Returns whether the option is critical, i.e. has an odd number
+    /// (RFC 7252, section 5.4.1).
+    pub fn is_critical(&self) -> bool {
+        u16::from(*self) & 1 == 1
+    }
+
+    /// Returns whether the option is marked NoCacheKey (RFC 7252,
+    /// section 5.4.2).
+    pub fn is_no_cache_key(&self) -> bool {
+        u16::from(*self) & 0x1E == 0x1C
+    }
+
+    /// Returns the value format the option is registered with. Unknown
+    /// options are treated as opaque.
+    pub fn value_format(&self) -> OptionValueFormat {
+        match self {
+            CoapOption::IfNoneMatch => OptionValueFormat::Empty,
+            CoapOption::IfMatch
+            | CoapOption::ETag
+            | CoapOption::Oscore
+            | CoapOption::RequestTag
+            | CoapOption::Unknown(_) => OptionValueFormat::Opaque,
+            CoapOption::Observe
+            | CoapOption::UriPort
+            | CoapOption::ContentFormat
+            | CoapOption::MaxAge
+            | CoapOption::Accept
+            | CoapOption::Block2
+            | CoapOption::Block1
+            | CoapOption::Size1
+            | CoapOption::Size2
+            | CoapOption::NoResponse => OptionValueFormat::Uint,
+            CoapOption::UriHost
+            | CoapOption::LocationPath
+            | CoapOption::UriPath
+            | CoapOption::UriQuery
+            | CoapOption::LocationQuery
+            | CoapOption::ProxyUri
+            | CoapOption::ProxyScheme => OptionValueFormat::String,
+        }
+    }
+
+    /// Returns whether the option may occur more than once (RFC 7252,
+    /// section 5.10, and RFC 9175 for Request-Tag). Unknown options are
+    /// treated as repeatable.
+    pub fn is_repeatable(&self) -> bool {
+        matches!(
+            self,
+            CoapOption::IfMatch
+                | CoapOption::ETag
+                | CoapOption::LocationPath
+                | CoapOption::UriPath
+                | CoapOption::UriQuery
+                | CoapOption::LocationQuery
+                | CoapOption::RequestTag
+                | CoapOption::Unknown(_)
+        )
+    }
+
+    /// Returns the maximum value length of a uint option.
+    fn max_uint_length(&self) -> Option<usize> {
+        match self {
+            CoapOption::NoResponse => Some(1),
+            CoapOption::UriPort | CoapOption::ContentFormat | CoapOption::Accept => Some(2),
+            CoapOption::Observe | CoapOption::Block1 | CoapOption::Block2 => Some(3),
+            CoapOption::MaxAge | CoapOption::Size1 | CoapOption::Size2 => Some(4),
+            _ => None,
         }
     }
 }
 
 /// The content formats.
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 #[non_exhaustive]
 pub enum ContentFormat {
     TextPlain,
@@ -339,140 +654,136 @@ pub enum ContentFormat {
     ImageSvgXml,
 }
 
+number_table! {
+    /// The numbers of the content formats.
+    pub CONTENT_FORMAT_TABLE: usize => ContentFormat {
+        0 => TextPlain,
+        16 => ApplicationCoseEncrypt0,
+        17 => ApplicationCoseMac0,
+        18 => ApplicationCoseSign1,
+        19 => ApplicationAceCbor,
+        21 => ImageGif,
+        22 => ImageJpeg,
+        23 => ImagePng,
+        40 => ApplicationLinkFormat,
+        41 => ApplicationXML,
+        42 => ApplicationOctetStream,
+        47 => ApplicationEXI,
+        50 => ApplicationJSON,
+        51 => ApplicationJsonPatchJson,
+        52 => ApplicationMergePatchJson,
+        60 => ApplicationCBOR,
+        61 => ApplicationCWt,
+        62 => ApplicationMultipartCore,
+        63 => ApplicationCborSeq,
+        96 => ApplicationCoseEncrypt,
+        97 => ApplicationCoseMac,
+        98 => ApplicationCoseSign,
+        101 => ApplicationCoseKey,
+        102 => ApplicationCoseKeySet,
+        110 => ApplicationSenmlJSON,
+        111 => ApplicationSensmlJSON,
+        112 => ApplicationSenmlCBOR,
+        113 => ApplicationSensmlCBOR,
+        114 => ApplicationSenmlExi,
+        115 => ApplicationSensmlExi,
+        140 => ApplicationYangDataCborSid,
+        256 => ApplicationCoapGroupJson,
+        271 => ApplicationDotsCbor,
+        272 => ApplicationMissingBlocksCborSeq,
+        280 => ApplicationPkcs7MimeServerGeneratedKey,
+        281 => ApplicationPkcs7MimeCertsOnly,
+        284 => ApplicationPkcs8,
+        285 => ApplicationCsrattrs,
+        286 => ApplicationPkcs10,
+        287 => ApplicationPkixCert,
+        290 => ApplicationAifCbor,
+        291 => ApplicationAifJson,
+        310 => ApplicationSenmlXML,
+        311 => ApplicationSensmlXML,
+        320 => ApplicationSenmlEtchJson,
+        322 => ApplicationSenmlEtchCbor,
+        340 => ApplicationYangDataCbor,
+        341 => ApplicationYangDataCborName,
+        432 => ApplicationTdJson,
+        836 => ApplicationVoucherCoseCbor,
+        10000 => ApplicationVndOcfCbor,
+        10001 => ApplicationOscore,
+        10002 => ApplicationJavascript,
+        11050 => ApplicationJsonDeflate,
+        11060 => ApplicationCborDeflate,
+        11542 => ApplicationVndOmaLwm2mTlv,
+        11543 => ApplicationVndOmaLwm2mJson,
+        11544 => ApplicationVndOmaLwm2mCbor,
+        20000 => TextCss,
+        30000 => ImageSvgXml,
+    }
+}
+
 impl TryFrom<usize> for ContentFormat {
     type Error = InvalidContentFormat;
 
     fn try_from(number: usize) -> Result<ContentFormat, InvalidContentFormat> {
-        match number {
-            0 => Ok(ContentFormat::TextPlain),
-            16 => Ok(ContentFormat::ApplicationCoseEncrypt0),
-            17 => Ok(ContentFormat::ApplicationCoseMac0),
-            18 => Ok(ContentFormat::ApplicationCoseSign1),
-            19 => Ok(ContentFormat::ApplicationAceCbor),
-            21 => Ok(ContentFormat::ImageGif),
-            22 => Ok(ContentFormat::ImageJpeg),
-            23 => Ok(ContentFormat::ImagePng),
-            40 => Ok(ContentFormat::ApplicationLinkFormat),
-            41 => Ok(ContentFormat::ApplicationXML),
-            42 => Ok(ContentFormat::ApplicationOctetStream),
-            47 => Ok(ContentFormat::ApplicationEXI),
-            50 => Ok(ContentFormat::ApplicationJSON),
-            51 => Ok(ContentFormat::ApplicationJsonPatchJson),
-            52 => Ok(ContentFormat::ApplicationMergePatchJson),
-            60 => Ok(ContentFormat::ApplicationCBOR),
-            61 => Ok(ContentFormat::ApplicationCWt),
-            62 => Ok(ContentFormat::ApplicationMultipartCore),
-            63 => Ok(ContentFormat::ApplicationCborSeq),
-            96 => Ok(ContentFormat::ApplicationCoseEncrypt),
-            97 => Ok(ContentFormat::ApplicationCoseMac),
-            98 => Ok(ContentFormat::ApplicationCoseSign),
-            101 => Ok(ContentFormat::ApplicationCoseKey),
-            102 => Ok(ContentFormat::ApplicationCoseKeySet),
-            110 => Ok(ContentFormat::ApplicationSenmlJSON),
-            111 => Ok(ContentFormat::ApplicationSensmlJSON),
-            112 => Ok(ContentFormat::ApplicationSenmlCBOR),
-            113 => Ok(ContentFormat::ApplicationSensmlCBOR),
-            114 => Ok(ContentFormat::ApplicationSenmlExi),
-            115 => Ok(ContentFormat::ApplicationSensmlExi),
-            140 => Ok(ContentFormat::ApplicationYangDataCborSid),
-            256 => Ok(ContentFormat::ApplicationCoapGroupJson),
-            271 => Ok(ContentFormat::ApplicationDotsCbor),
-            272 => Ok(ContentFormat::ApplicationMissingBlocksCborSeq),
-            280 => Ok(ContentFormat::ApplicationPkcs7MimeServerGeneratedKey),
-            281 => Ok(ContentFormat::ApplicationPkcs7MimeCertsOnly),
-            284 => Ok(ContentFormat::ApplicationPkcs8),
-            285 => Ok(ContentFormat::ApplicationCsrattrs),
-            286 => Ok(ContentFormat::ApplicationPkcs10),
-            287 => Ok(ContentFormat::ApplicationPkixCert),
-            290 => Ok(ContentFormat::ApplicationAifCbor),
-            291 => Ok(ContentFormat::ApplicationAifJson),
-            310 => Ok(ContentFormat::ApplicationSenmlXML),
-            311 => Ok(ContentFormat::ApplicationSensmlXML),
-            320 => Ok(ContentFormat::ApplicationSenmlEtchJson),
-            322 => Ok(ContentFormat::ApplicationSenmlEtchCbor),
-            340 => Ok(ContentFormat::ApplicationYangDataCbor),
-            341 => Ok(ContentFormat::ApplicationYangDataCborName),
-            432 => Ok(ContentFormat::ApplicationTdJson),
-            836 => Ok(ContentFormat::ApplicationVoucherCoseCbor),
-            10000 => Ok(ContentFormat::ApplicationVndOcfCbor),
-            10001 => Ok(ContentFormat::ApplicationOscore),
-            10002 => Ok(ContentFormat::ApplicationJavascript),
-            11050 => Ok(ContentFormat::ApplicationJsonDeflate),
-            11060 => Ok(ContentFormat::ApplicationCborDeflate),
-            11542 => Ok(ContentFormat::ApplicationVndOmaLwm2mTlv),
-            11543 => Ok(ContentFormat::ApplicationVndOmaLwm2mJson),
-            11544 => Ok(ContentFormat::ApplicationVndOmaLwm2mCbor),
-            20000 => Ok(ContentFormat::TextCss),
-            30000 => Ok(ContentFormat::ImageSvgXml),
-            _ => Err(InvalidContentFormat),
-        }
+        ContentFormat::from_number(number).ok_or(InvalidContentFormat)
     }
 }
 
-impl From<ContentFormat> for usize {
-    fn from(format: ContentFormat) -> usize {
-        match format {
-            ContentFormat::TextPlain => 0,
-            ContentFormat::ApplicationCoseEncrypt0 => 16,
-            ContentFormat::ApplicationCoseMac0 => 17,
-            ContentFormat::ApplicationCoseSign1 => 18,
-            ContentFormat::ApplicationAceCbor => 19,
-            ContentFormat::ImageGif => 21,
-            ContentFormat::ImageJpeg => 22,
-            ContentFormat::ImagePng => 23,
-            ContentFormat::ApplicationLinkFormat => 40,
-            ContentFormat::ApplicationXML => 41,
-            ContentFormat::ApplicationOctetStream => 42,
-            ContentFormat::ApplicationEXI => 47,
-            ContentFormat::ApplicationJSON => 50,
-            ContentFormat::ApplicationJsonPatchJson => 51,
-            ContentFormat::ApplicationMergePatchJson => 52,
-            ContentFormat::ApplicationCBOR => 60,
-            ContentFormat::ApplicationCWt => 61,
-            ContentFormat::ApplicationMultipartCore => 62,
-            ContentFormat::ApplicationCborSeq => 63,
-            ContentFormat::ApplicationCoseEncrypt => 96,
-            ContentFormat::ApplicationCoseMac => 97,
-            ContentFormat::ApplicationCoseSign => 98,
-            ContentFormat::ApplicationCoseKey => 101,
-            ContentFormat::ApplicationCoseKeySet => 102,
-            ContentFormat::ApplicationSenmlJSON => 110,
-            ContentFormat::ApplicationSensmlJSON => 111,
-            ContentFormat::ApplicationSenmlCBOR => 112,
-            ContentFormat::ApplicationSensmlCBOR => 113,
-            ContentFormat::ApplicationSenmlExi => 114,
-            ContentFormat::ApplicationSensmlExi => 115,
-            ContentFormat::ApplicationYangDataCborSid => 140,
-            ContentFormat::ApplicationCoapGroupJson => 256,
-            ContentFormat::ApplicationDotsCbor => 271,
-            ContentFormat::ApplicationMissingBlocksCborSeq => 272,
-            ContentFormat::ApplicationPkcs7MimeServerGeneratedKey => 280,
-            ContentFormat::ApplicationPkcs7MimeCertsOnly => 281,
-            ContentFormat::ApplicationPkcs8 => 284,
-            ContentFormat::ApplicationCsrattrs => 285,
-            ContentFormat::ApplicationPkcs10 => 286,
-            ContentFormat::ApplicationPkixCert => 287,
-            ContentFormat::ApplicationAifCbor => 290,
-            ContentFormat::ApplicationAifJson => 291,
-            ContentFormat::ApplicationSenmlXML => 310,
-            ContentFormat::ApplicationSensmlXML => 311,
-            ContentFormat::ApplicationSenmlEtchJson => 320,
-            ContentFormat::ApplicationSenmlEtchCbor => 322,
-            ContentFormat::ApplicationYangDataCbor => 340,
-            ContentFormat::ApplicationYangDataCborName => 341,
-            ContentFormat::ApplicationTdJson => 432,
-            ContentFormat::ApplicationVoucherCoseCbor => 836,
-            ContentFormat::ApplicationVndOcfCbor => 10000,
-            ContentFormat::ApplicationOscore => 10001,
-            ContentFormat::ApplicationJavascript => 10002,
-            ContentFormat::ApplicationJsonDeflate => 11050,
-            ContentFormat::ApplicationCborDeflate => 11060,
-            ContentFormat::ApplicationVndOmaLwm2mTlv => 11542,
-            ContentFormat::ApplicationVndOmaLwm2mJson => 11543,
-            ContentFormat::ApplicationVndOmaLwm2mCbor => 11544,
-            ContentFormat::TextCss => 20000,
-            ContentFormat::ImageSvgXml => 30000,
-        }
+impl ContentFormat {
+    /// Returns whether the format is one of the SenML/SenSML formats.
+    pub fn is_senml(&self) -> bool {
+        matches!(
+            self,
+            ContentFormat::ApplicationSenmlJSON
+                | ContentFormat::ApplicationSensmlJSON
+                | ContentFormat::ApplicationSenmlCBOR
+                | ContentFormat::ApplicationSensmlCBOR
+                | ContentFormat::ApplicationSenmlExi
+                | ContentFormat::ApplicationSensmlExi
+                | ContentFormat::ApplicationSenmlXML
+                | ContentFormat::ApplicationSensmlXML
+                | ContentFormat::ApplicationSenmlEtchJson
+                | ContentFormat::ApplicationSenmlEtchCbor
+        )
+    }
+
+    /// Returns whether the format is CBOR based.
+    pub fn is_cbor(&self) -> bool {
+        matches!(
+            self,
+            ContentFormat::ApplicationCBOR
+                | ContentFormat::ApplicationCborSeq
+                | ContentFormat::ApplicationCWt
+                | ContentFormat::ApplicationAceCbor
+                | ContentFormat::ApplicationSenmlCBOR
+                | ContentFormat::ApplicationSensmlCBOR
+                | ContentFormat::ApplicationYangDataCborSid
+                | ContentFormat::ApplicationDotsCbor
+                | ContentFormat::ApplicationMissingBlocksCborSeq
+                | ContentFormat::ApplicationAifCbor
+                | ContentFormat::ApplicationSenmlEtchCbor
+                | ContentFormat::ApplicationYangDataCbor
+                | ContentFormat::ApplicationYangDataCborName
+                | ContentFormat::ApplicationVoucherCoseCbor
+                | ContentFormat::ApplicationVndOcfCbor
+                | ContentFormat::ApplicationVndOmaLwm2mCbor
+        )
+    }
+
+    /// Returns whether the format is JSON based.
+    pub fn is_json(&self) -> bool {
+        matches!(
+            self,
+            ContentFormat::ApplicationJSON
+                | ContentFormat::ApplicationJsonPatchJson
+                | ContentFormat::ApplicationMergePatchJson
+                | ContentFormat::ApplicationSenmlJSON
+                | ContentFormat::ApplicationSensmlJSON
+                | ContentFormat::ApplicationCoapGroupJson
+                | ContentFormat::ApplicationAifJson
+                | ContentFormat::ApplicationSenmlEtchJson
+                | ContentFormat::ApplicationTdJson
+                | ContentFormat::ApplicationVndOmaLwm2mJson
+        )
     }
 }
 
@@ -504,22 +815,262 @@ impl From<ObserveOption> for usize {
     }
 }
 
+/// Generates Observe sequence numbers for notifications, wrapping at 24
+/// bits (RFC 7641, section 4.4).
+#[derive(Debug, Clone, Default)]
+pub struct ObserveSeqCounter {
+    next: u32,
+}
+
+impl ObserveSeqCounter {
+    pub fn new() -> Self {
+        ObserveSeqCounter { next: 0 }
+    }
+
+    /// Starts the sequence at the given value, truncated to 24 bits.
+    pub fn starting_at(seq: u32) -> Self {
+        ObserveSeqCounter {
+            next: seq & 0xFF_FFFF,
+        }
+    }
+
+    /// Returns the next sequence number.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> u32 {
+        let seq = self.next;
+        self.next = (seq + 1) & 0xFF_FFFF;
+        seq
+    }
+}
+
+/// The value of a Block1 or Block2 option (RFC 7959, section 2.2).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockValue {
+    pub num: u16,
+    pub more: bool,
+    /// SZX, the block size is 2^(size_exponent + 4) bytes.
+    pub size_exponent: u8,
+}
+
+impl BlockValue {
+    /// Creates a block value, the size must be a power of two between 16
+    /// and 1024 bytes.
+    pub fn new(num: usize, more: bool, size: usize) -> Result<Self, InvalidBlockValue> {
+        let num = u16::try_from(num).map_err(InvalidBlockValue::TypeBoundsError)?;
+        if !size.is_power_of_two() || !(16..=1024).contains(&size) {
+            return Err(InvalidBlockValue::SizeExponentEncodingError(size));
+        }
+        Ok(BlockValue {
+            num,
+            more,
+            size_exponent: (size.trailing_zeros() - 4) as u8,
+        })
+    }
+
+    /// Returns the block size in bytes.
+    pub fn size(&self) -> usize {
+        1 << (self.size_exponent + 4)
+    }
+
+    /// Encodes the value into `buf`, using at least one byte.
+    pub fn encode<'b>(&self, buf: &'b mut [u8; 3]) -> &'b [u8] {
+        let raw = (self.num as u32) << 4 | (self.more as u32) << 3 | self.size_exponent as u32;
+        let bytes = raw.to_be_bytes();
+        let len = (4 - raw.leading_zeros() as usize / 8).max(1);
+        buf[..len].copy_from_slice(&bytes[4 - len..]);
+        &buf[..len]
+    }
+}
+
+impl TryFrom<&[u8]> for BlockValue {
+    type Error = InvalidBlockValue;
+
+    fn try_from(value: &[u8]) -> Result<Self, InvalidBlockValue> {
+        let raw = value
+            .iter()
+            .fold(0u64, |acc, &b| acc.saturating_mul(256) | b as u64);
+        let size_exponent = (raw & 0x7) as u8;
+        if size_exponent == 7 {
+            return Err(InvalidBlockValue::SizeExponentEncodingError(2048));
+        }
+        Ok(BlockValue {
+            num: u16::try_from(raw >> 4).map_err(InvalidBlockValue::TypeBoundsError)?,
+            more: raw & 0x8 != 0,
+            size_exponent,
+        })
+    }
+}
+
+/// The message ID and token of an exchange, with the token stored inline so
+/// it can be used as a map key.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TransactionId {
+    pub message_id: u16,
+    pub token: Vec<u8, 8>,
+}
+
+impl<const N: usize> TryFrom<&Packet<'_, N>> for TransactionId {
+    type Error = MessageError;
+
+    /// Returns InvalidTokenLength for RFC 8974 tokens longer than 8 bytes.
+    fn try_from(packet: &Packet<'_, N>) -> Result<Self, MessageError> {
+        let (message_id, token) = packet.transaction_key();
+        Ok(TransactionId {
+            message_id,
+            token: Vec::from_slice(token).map_err(|_| MessageError::InvalidTokenLength)?,
+        })
+    }
+}
+
 #[derive(Debug, Clone, Copy, Default, PartialEq)]
 pub struct OptionPair<'a> {
     pub num: u16,
     pub value: &'a [u8],
 }
 
+impl OptionPair<'_> {
+    /// Returns the value as an array if it is exactly N bytes long.
+    pub fn value_array<const N: usize>(&self) -> Option<[u8; N]> {
+        self.value.try_into().ok()
+    }
+}
+
+/// Iterates over the options encoded in a standalone options region, e.g.
+/// one framed by a transport other than UDP. Stops at the payload marker or
+/// after the first error.
+#[derive(Debug, Clone)]
+pub struct RawOptions<'a> {
+    bytes: &'a [u8],
+    idx: usize,
+    number: u16,
+}
+
+impl<'a> RawOptions<'a> {
+    /// Creates the iterator, the first option delta is relative to
+    /// `start_number`.
+    pub fn new(bytes: &'a [u8], start_number: u16) -> Self {
+        RawOptions {
+            bytes,
+            idx: 0,
+            number: start_number,
+        }
+    }
+}
+
+impl<'a> Iterator for RawOptions<'a> {
+    type Item = Result<OptionPair<'a>, MessageError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match Packet::read_option(self.bytes, self.idx, self.number) {
+            Ok(Some((option, next))) => {
+                self.idx = next;
+                self.number = option.num;
+                Some(Ok(option))
+            }
+            Ok(None) => None,
+            Err(err) => {
+                self.idx = self.bytes.len();
+                Some(Err(err.kind))
+            }
+        }
+    }
+}
+
+/// Whether a buffer holds the complete packet header and token.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BufferStatus {
+    /// At least the given number of additional bytes is needed.
+    Incomplete(usize),
+    Complete,
+    /// The header declares a token length above 8, so no number of
+    /// additional bytes makes the packet valid.
+    Invalid,
+}
+
 #[derive(Debug, Clone, PartialEq)]
-pub struct Packet<'a> {
+pub struct Packet<'a, const N: usize = MAX_OPTIONS> {
     /// Version, message type and token length byte.
     ver_type_tkl: u8,
     code: MessageClass,
     message_id: u16,
     pub token: &'a [u8],
     /// Sorted by OptionPair.num vector of options.
-    pub options: Vec<OptionPair<'a>, MAX_OPTIONS>,
+    pub options: Vec<OptionPair<'a>, N>,
     pub payload: &'a [u8],
+    /// The buffer the packet was decoded from, empty for built packets.
+    source: &'a [u8],
+    /// Offset of the first option header within `source`.
+    options_start: usize,
+    /// Set by `from_bytes_with_truncation` when the buffer is shorter than
+    /// the declared datagram.
+    payload_truncated: bool,
+    /// Set by `from_bytes_lenient` when the token length was clamped.
+    token_nonconformant: bool,
+}
+
+/// How the token length nibble is interpreted when decoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TokenPolicy {
+    /// Tokens of up to 8 bytes, as required by RFC 7252.
+    Strict,
+    /// Also the extended token lengths of RFC 8974.
+    Extended,
+    /// Also the lengths 9 to 15, skipping the declared number of bytes but
+    /// clamping the token to 8 bytes.
+    Lenient,
+}
+
+/// Decodes packets into option storage kept in the decoder, filling the
+/// stored packet in place instead of building a new one for every call.
+/// The decoded packet is borrowed from the decoder and borrows the buffer,
+/// so the decoded buffers must outlive the decoder.
+#[derive(Debug, Clone)]
+pub struct PacketDecoder<'b> {
+    packet: Packet<'b>,
+}
+
+impl Default for PacketDecoder<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'b> PacketDecoder<'b> {
+    pub const fn new() -> Self {
+        PacketDecoder {
+            packet: Packet::empty(MessageType::Confirmable, 0),
+        }
+    }
+
+    /// Decodes the buffer, replacing the previously decoded packet. On error
+    /// the decoder is reset to an empty packet.
+    pub fn decode(&mut self, buf: &'b [u8]) -> Result<&Packet<'b>, MessageError> {
+        let packet = &mut self.packet;
+        packet
+            .decode_into(buf, TokenPolicy::Strict, usize::MAX)
+            .map_err(|err| {
+                packet.clear();
+                err.kind
+            })?;
+        Ok(packet)
+    }
+}
+
+/// Storage for the bytes generated by `Packet::chunks`: the fixed header
+/// and the header of each option.
+#[derive(Debug, Clone, Default)]
+pub struct ChunkHeaders<const N: usize = MAX_OPTIONS> {
+    header: Vec<u8, 4>,
+    options: Vec<Vec<u8, 5>, N>,
+}
+
+impl<const N: usize> ChunkHeaders<N> {
+    pub const fn new() -> Self {
+        ChunkHeaders {
+            header: Vec::new(),
+            options: Vec::new(),
+        }
+    }
 }
 
 impl<'a> Packet<'a> {
@@ -542,9 +1093,17 @@ impl<'a> Packet<'a> {
         let mut ver_type_tkl = version << 6;
         // Set type.
         ver_type_tkl = tn << 4 | (0xCF & ver_type_tkl);
-        // Set token length.
-        assert_eq!(0xF0 & token.len(), 0);
-        ver_type_tkl = (token.len() as u8) | (0xF0 & ver_type_tkl);
+        // Set token length, tokens longer than 12 bytes use the RFC 8974
+        // extended token length.
+        let tkl = match token.len() {
+            0..=12 => token.len() as u8,
+            13..=268 => 13,
+            _ => {
+                assert!(token.len() <= EXTENDED_TOKEN_MAX_SIZE);
+                14
+            }
+        };
+        ver_type_tkl = tkl | (0xF0 & ver_type_tkl);
 
         Self::sort_option_pairs(options);
 
@@ -555,9 +1114,89 @@ impl<'a> Packet<'a> {
             token,
             options: Vec::<OptionPair<'a>, MAX_OPTIONS>::from_iter(options.iter().cloned()),
             payload,
+            source: &[],
+            options_start: 0,
+            payload_truncated: false,
+            token_nonconformant: false,
+        }
+    }
+
+    /// Returns a builder for an empty version 1 Confirmable message.
+    pub fn builder() -> PacketBuilder<'a> {
+        PacketBuilder::new()
+    }
+
+    /// Appends the typed options to `out`, for use with `new`.
+    pub fn options_from_pairs<'b>(
+        pairs: &[(CoapOption, &'b [u8])],
+        out: &mut Vec<OptionPair<'b>, MAX_OPTIONS>,
+    ) -> Result<(), MessageError> {
+        for &(option, value) in pairs {
+            out.push(OptionPair {
+                num: option.into(),
+                value,
+            })
+            .map_err(|_| MessageError::OptionsLimitExceeded)?;
+        }
+        Ok(())
+    }
+
+    /// Creates an empty Acknowledgement message.
+    pub const fn empty_ack(message_id: u16) -> Packet<'static> {
+        Self::empty(MessageType::Acknowledgement, message_id)
+    }
+
+    /// Creates an empty Reset message.
+    pub const fn empty_reset(message_id: u16) -> Packet<'static> {
+        Self::empty(MessageType::Reset, message_id)
+    }
+
+    /// Creates the empty Reset message rejecting the received packet.
+    pub fn reset_for<const M: usize>(received: &Packet<'_, M>) -> Packet<'static> {
+        Self::empty_reset(received.get_message_id())
+    }
+
+    /// Creates an empty Confirmable message (CoAP ping).
+    pub const fn empty_confirmable(message_id: u16) -> Packet<'static> {
+        Self::empty(MessageType::Confirmable, message_id)
+    }
+
+    const fn empty<const M: usize>(t: MessageType, message_id: u16) -> Packet<'static, M> {
+        let tn = match t {
+            MessageType::Confirmable => 0,
+            MessageType::NonConfirmable => 1,
+            MessageType::Acknowledgement => 2,
+            MessageType::Reset => 3,
+        };
+        Packet {
+            ver_type_tkl: 1 << 6 | tn << 4,
+            code: MessageClass::Empty,
+            message_id,
+            token: &[],
+            options: Vec::new(),
+            payload: &[],
+            source: &[],
+            options_start: 0,
+            payload_truncated: false,
+            token_nonconformant: false,
         }
     }
 
+    /// Puts the packet back into the state of `Packet::empty_confirmable(0)`,
+    /// keeping the option storage.
+    fn clear(&mut self) {
+        self.ver_type_tkl = 1 << 6;
+        self.code = MessageClass::Empty;
+        self.message_id = 0;
+        self.token = &[];
+        self.options.clear();
+        self.payload = &[];
+        self.source = &[];
+        self.options_start = 0;
+        self.payload_truncated = false;
+        self.token_nonconformant = false;
+    }
+
     fn sort_option_pairs<'b, const MAX_OPTIONS: usize>(vec: &mut Vec<OptionPair<'b>, MAX_OPTIONS>) {
         let len = vec.len();
         for i in 1..len {
@@ -569,185 +1208,284 @@ impl<'a> Packet<'a> {
         }
     }
 
-    #[inline]
-    pub fn get_version(&self) -> u8 {
-        self.ver_type_tkl >> 6
-    }
-
-    #[inline]
-    pub fn get_type(&self) -> MessageType {
-        let tn = (0x30 & self.ver_type_tkl) >> 4;
-        match tn {
-            0 => MessageType::Confirmable,
-            1 => MessageType::NonConfirmable,
-            2 => MessageType::Acknowledgement,
-            3 => MessageType::Reset,
-            _ => unreachable!(),
+    /// Checks whether the buffer is long enough for the fixed header and
+    /// the token it declares. Options and payload have no declared length
+    /// in a datagram, so they can't be checked.
+    pub fn needs_more_bytes(buf: &[u8]) -> BufferStatus {
+        if buf.len() < 4 {
+            return BufferStatus::Incomplete(4 - buf.len());
+        }
+        let token_length = Self::get_token_length_internal(buf[0]);
+        if token_length > 8 {
+            return BufferStatus::Invalid;
+        }
+        let required = 4 + token_length as usize;
+        if buf.len() < required {
+            BufferStatus::Incomplete(required - buf.len())
+        } else {
+            BufferStatus::Complete
         }
     }
 
-    #[inline]
-    pub fn get_token_length(&self) -> u8 {
-        Self::get_token_length_internal(self.ver_type_tkl)
+    pub fn from_bytes<'b>(buf: &'b [u8]) -> Result<Packet<'b>, MessageError> {
+        Self::decode(buf, TokenPolicy::Strict, usize::MAX).map_err(|err| err.kind)
     }
 
-    fn get_token_length_internal(ver_type_tkl: u8) -> u8 {
-        0x0F & ver_type_tkl
+    /// Same as `from_bytes`, for a buffer holding the first bytes of a
+    /// datagram of `declared_total` bytes. The packet is marked as truncated
+    /// if bytes are missing, see `is_payload_truncated`.
+    pub fn from_bytes_with_truncation<'b>(
+        buf: &'b [u8],
+        declared_total: usize,
+    ) -> Result<Packet<'b>, MessageError> {
+        let mut packet = Self::from_bytes(buf)?;
+        packet.payload_truncated = buf.len() < declared_total;
+        Ok(packet)
     }
 
-    #[inline]
-    pub fn get_message_id(&self) -> u16 {
-        self.message_id
+    /// Same as `from_bytes`, but accepts the token lengths 9 to 15 sent by
+    /// non-conformant peers. The declared number of token bytes is skipped,
+    /// while the token is clamped to its first 8 bytes and the packet is
+    /// marked, see `is_token_nonconformant`.
+    pub fn from_bytes_lenient<'b>(buf: &'b [u8]) -> Result<Packet<'b>, MessageError> {
+        Self::decode(buf, TokenPolicy::Lenient, usize::MAX).map_err(|err| err.kind)
     }
 
-    pub fn options(&self) -> core::slice::Iter<'_, OptionPair<'_>> {
-        self.options.iter()
+    /// Same as `from_bytes`, but returns InvalidOptionLength if the encoded
+    /// options take more than `max_option_bytes` bytes.
+    pub fn from_bytes_limited<'b>(
+        buf: &'b [u8],
+        max_option_bytes: usize,
+    ) -> Result<Packet<'b>, MessageError> {
+        Self::decode(buf, TokenPolicy::Strict, max_option_bytes).map_err(|err| err.kind)
     }
 
-    pub fn get_code(&self) -> MessageClass {
-        self.code
+    /// Same as `from_bytes`, but with room for `M` options, which allows
+    /// parsing packets with more than MAX_OPTIONS options.
+    pub fn from_bytes_in<'b, const M: usize>(buf: &'b [u8]) -> Result<Packet<'b, M>, MessageError> {
+        Self::decode(buf, TokenPolicy::Strict, usize::MAX).map_err(|err| err.kind)
     }
 
-    pub fn get_token(&self) -> &[u8] {
-        &self.token
+    /// Runs the same checks as `from_bytes` without building a Packet, for
+    /// callers that forward the original bytes verbatim.
+    pub fn validate_bytes(buf: &[u8]) -> Result<(), MessageError> {
+        let options_start = Self::try_header(buf)
+            .and_then(|header| Self::token_span(buf, header.0, TokenPolicy::Strict))?
+            .2;
+        let mut count = 0;
+        Self::walk_options(buf, options_start, usize::MAX, |_| {
+            count += 1;
+            if count > MAX_OPTIONS {
+                return Err(MessageError::OptionsLimitExceeded);
+            }
+            Ok(())
+        })
+        .map_err(|err| err.kind)?;
+        Ok(())
     }
 
-    pub fn get_payload(&self) -> &[u8] {
-        &self.payload
+    /// Same as `from_bytes`, but the error also carries the offset of the
+    /// byte where decoding failed.
+    pub fn from_bytes_detailed<'b>(buf: &'b [u8]) -> Result<Packet<'b>, DetailedMessageError> {
+        Self::decode(buf, TokenPolicy::Strict, usize::MAX)
     }
 
-    pub fn get_options(&self, tp: CoapOption) -> impl Iterator<Item = &OptionPair<'a>> {
-        self.options.iter().filter(move |&p| p.num == tp.into())
+    /// Same as `from_bytes`, but also accepts tokens longer than 8 bytes
+    /// using the extended token length of RFC 8974.
+    pub fn from_bytes_extended_token<'b>(buf: &'b [u8]) -> Result<Packet<'b>, MessageError> {
+        Self::decode(buf, TokenPolicy::Extended, usize::MAX).map_err(|err| err.kind)
     }
 
-    pub fn get_first_option(&self, tp: CoapOption) -> Option<&OptionPair<'a>> {
-        self.options.iter().find(|&p| p.num == tp.into())
+    fn decode<'b, const M: usize>(
+        buf: &'b [u8],
+        policy: TokenPolicy,
+        max_option_bytes: usize,
+    ) -> Result<Packet<'b, M>, DetailedMessageError> {
+        let mut packet = Self::empty(MessageType::Confirmable, 0);
+        packet.decode_into(buf, policy, max_option_bytes)?;
+        Ok(packet)
     }
 
-    pub fn get_content_format_value(&self) -> Option<u16> {
-        self.get_first_option(CoapOption::ContentFormat)
-            .map(|option| self.to_uint::<u16>(option.value))
-            .and_then(|value| value.ok())
-    }
-
-    pub fn get_observe_value(&self) -> Option<u32> {
-        self.get_first_option(CoapOption::Observe)
-            .map(|option| self.to_uint::<u32>(option.value))
-            .and_then(|value| value.ok())
-    }
-
-    pub fn from_bytes<'b>(buf: &'b [u8]) -> Result<Packet<'b>, MessageError> {
-        let header_result = Self::try_header(buf);
-        if header_result.is_err() {
-            return Err(header_result.unwrap_err());
-        }
-        let raw_header = header_result.unwrap();
-        let token_length = Self::get_token_length_internal(raw_header.0);
-        let options_start: usize = 4 + token_length as usize;
-
-        if token_length > 8 {
-            return Err(MessageError::InvalidTokenLength);
-        }
+    /// Returns the offsets where the token starts, where it ends, and where
+    /// the options begin.
+    fn token_span(
+        buf: &[u8],
+        ver_type_tkl: u8,
+        policy: TokenPolicy,
+    ) -> Result<(usize, usize, usize), MessageError> {
+        let token_length = Self::get_token_length_internal(ver_type_tkl);
+        let extended = policy == TokenPolicy::Extended;
+        let (token_start, token_length) = match token_length {
+            0..=8 => (4, token_length as usize),
+            9..=15 if policy == TokenPolicy::Lenient => {
+                let options_start = 4 + token_length as usize;
+                if options_start > buf.len() {
+                    return Err(MessageError::InvalidTokenLength);
+                }
+                return Ok((4, 12, options_start));
+            }
+            9..=12 if extended => (4, token_length as usize),
+            13 if extended => {
+                let extension = buf.get(4).ok_or(MessageError::InvalidTokenLength)?;
+                (5, *extension as usize + 13)
+            }
+            14 if extended => {
+                let extension = buf.get(4..6).ok_or(MessageError::InvalidTokenLength)?;
+                (
+                    6,
+                    u16::from_be_bytes([extension[0], extension[1]]) as usize + 269,
+                )
+            }
+            _ => return Err(MessageError::InvalidTokenLength),
+        };
+        let options_start = token_start + token_length;
 
         if options_start > buf.len() {
             return Err(MessageError::InvalidTokenLength);
         }
-        let token = &buf[4..options_start];
+        Ok((token_start, options_start, options_start))
+    }
 
-        let mut idx = options_start;
-        let mut options_number = 0;
-        let mut options = Vec::<OptionPair, MAX_OPTIONS>::new();
-        while idx < buf.len() {
-            let byte = buf[idx];
+    /// Decodes the options starting at the given offset into `options`,
+    /// returning the payload.
+    pub(crate) fn decode_options<'b, const M: usize>(
+        buf: &'b [u8],
+        start: usize,
+        options: &mut Vec<OptionPair<'b>, M>,
+    ) -> Result<&'b [u8], MessageError> {
+        Self::walk_options(buf, start, usize::MAX, |option| {
+            options
+                .push(option)
+                .map_err(|_| MessageError::OptionsLimitExceeded)
+        })
+        .map_err(|err| err.kind)
+    }
 
-            if byte == 255 || idx > buf.len() {
-                break;
+    /// Parses the options starting at the given offset, passing each one to
+    /// `visit`, and returns the payload. Fails with InvalidOptionLength as
+    /// soon as the options take more than `max_option_bytes` bytes. Errors
+    /// carry the offset of the option header or extended field at fault.
+    fn walk_options<'b, F>(
+        buf: &'b [u8],
+        start: usize,
+        max_option_bytes: usize,
+        mut visit: F,
+    ) -> Result<&'b [u8], DetailedMessageError>
+    where
+        F: FnMut(OptionPair<'b>) -> Result<(), MessageError>,
+    {
+        let mut idx = start;
+        let mut number = 0;
+        while let Some((option, next)) = Self::read_option(buf, idx, number)? {
+            if next - start > max_option_bytes {
+                return Err(DetailedMessageError {
+                    kind: MessageError::InvalidOptionLength,
+                    offset: idx,
+                });
             }
+            visit(option).map_err(|kind| DetailedMessageError { kind, offset: idx })?;
+            number = option.num;
+            idx = next;
+        }
 
-            let mut delta = (byte >> 4) as u16;
-            let mut length = (byte & 0xF) as usize;
+        let payload = if idx < buf.len() {
+            &buf[(idx + 1)..buf.len()]
+        } else {
+            &[0; 0]
+        };
 
-            idx += 1;
+        Ok(payload)
+    }
 
-            // Check for special delta characters
-            match delta {
-                13 => {
-                    if idx >= buf.len() {
-                        return Err(MessageError::InvalidOptionLength);
-                    }
-                    delta = (buf[idx] + 13).into();
-                    idx += 1;
-                }
-                14 => {
-                    if idx + 1 >= buf.len() {
-                        return Err(MessageError::InvalidOptionLength);
-                    }
+    /// Parses the option at the given offset, whose delta is relative to
+    /// `number`. Returns the option and the offset following it, or None at
+    /// the end of the buffer or the payload marker.
+    fn read_option(
+        buf: &[u8],
+        header_idx: usize,
+        number: u16,
+    ) -> Result<Option<(OptionPair<'_>, usize)>, DetailedMessageError> {
+        let fail = |kind, offset| Err(DetailedMessageError { kind, offset });
+        let byte = match buf.get(header_idx) {
+            Some(255) | None => return Ok(None),
+            Some(&byte) => byte,
+        };
 
-                    delta = u16::from_be(u8_to_unsigned_be!(buf, idx, idx + 1, u16)) + 269;
-                    idx += 2;
-                }
-                15 => {
-                    return Err(MessageError::InvalidOptionDelta);
-                }
-                _ => {}
-            };
+        let mut idx = header_idx;
+        let mut delta = (byte >> 4) as u16;
+        let mut length = (byte & 0xF) as usize;
 
-            // Check for special length characters
-            match length {
-                13 => {
-                    if idx >= buf.len() {
-                        return Err(MessageError::InvalidOptionLength);
-                    }
+        idx += 1;
 
-                    length = buf[idx] as usize + 13;
-                    idx += 1;
+        // Check for special delta characters
+        match delta {
+            13 => {
+                if idx >= buf.len() {
+                    return fail(MessageError::InvalidOptionLength, idx);
                 }
-                14 => {
-                    if idx + 1 >= buf.len() {
-                        return Err(MessageError::InvalidOptionLength);
-                    }
-
-                    length =
-                        (u16::from_be(u8_to_unsigned_be!(buf, idx, idx + 1, u16)) + 269) as usize;
-                    idx += 2;
+                delta = u16::from(buf[idx]) + 13;
+                idx += 1;
+            }
+            14 => {
+                if idx.checked_add(1).is_none_or(|last| last >= buf.len()) {
+                    return fail(MessageError::InvalidOptionLength, idx);
                 }
-                15 => {
-                    return Err(MessageError::InvalidOptionLength);
+
+                delta = match u16::from_be(u8_to_unsigned_be!(buf, idx, idx + 1, u16))
+                    .checked_add(269)
+                {
+                    Some(delta) => delta,
+                    None => return fail(MessageError::InvalidOptionDelta, idx),
+                };
+                idx += 2;
+            }
+            15 => {
+                return fail(MessageError::InvalidOptionDelta, header_idx);
+            }
+            _ => {}
+        };
+
+        // Check for special length characters
+        let length_idx = if length >= 13 { idx } else { header_idx };
+        match length {
+            13 => {
+                if idx >= buf.len() {
+                    return fail(MessageError::InvalidOptionLength, idx);
                 }
-                _ => {}
-            };
 
-            options_number += delta;
+                length = buf[idx] as usize + 13;
+                idx += 1;
+            }
+            14 => {
+                if idx.checked_add(1).is_none_or(|last| last >= buf.len()) {
+                    return fail(MessageError::InvalidOptionLength, idx);
+                }
 
-            let end = idx + length;
-            if end > buf.len() {
-                return Err(MessageError::InvalidOptionLength);
+                length = u16::from_be(u8_to_unsigned_be!(buf, idx, idx + 1, u16)) as usize + 269;
+                idx += 2;
             }
-            match options.push(OptionPair {
-                num: options_number,
-                value: &buf[idx..end],
-            }) {
-                Err(_) => return Err(MessageError::OptionsLimitExceeded),
-                _ => {}
+            15 => {
+                return fail(MessageError::InvalidOptionLength, header_idx);
             }
+            _ => {}
+        };
 
-            idx += length;
-        }
-
-        let payload = if idx < buf.len() {
-            &buf[(idx + 1)..buf.len()]
-        } else {
-            &[0; 0]
+        let num = match number.checked_add(delta) {
+            Some(num) => num,
+            None => return fail(MessageError::InvalidOptionDelta, header_idx),
         };
 
-        Ok(Packet {
-            ver_type_tkl: raw_header.0,
-            code: raw_header.1.into(),
-            message_id: raw_header.2,
-            token,
-            options: options,
-            payload: payload,
-        })
+        let end = match idx.checked_add(length) {
+            Some(end) if end <= buf.len() => end,
+            _ => return fail(MessageError::InvalidOptionLength, length_idx),
+        };
+        Ok(Some((
+            OptionPair {
+                num,
+                value: &buf[idx..end],
+            },
+            end,
+        )))
     }
 
     fn try_header(buf: &[u8]) -> Result<(u8, u8, u16), MessageError> {
@@ -760,315 +1498,3312 @@ impl<'a> Packet<'a> {
 
         Ok((buf[0], buf[1], u16::from_be_bytes(id_bytes)))
     }
+}
 
-    pub fn to_bytes(&self) -> Result<Vec<u8, PACKET_MAX_SIZE>, MessageError> {
-        let mut options_delta_length = 0;
-        let mut options_bytes: Vec<u8, PACKET_MAX_SIZE> = Vec::new();
-        let mut i = 0;
-        while i < self.options.len() {
-            let start_option_pair = self.options.get(i);
-            let mut j = i;
-            while j < self.options.len()
-                && start_option_pair.unwrap().num == self.options.get(j).unwrap().num
-            {
-                let value = self.options.get(j).unwrap().value;
-                let mut header = Vec::<u8, 5>::new();
-                let delta = start_option_pair.unwrap().num - options_delta_length;
-
-                let mut byte: u8 = 0;
-                if delta <= 12 {
-                    byte |= (delta << 4) as u8;
-                } else if delta < 269 {
-                    byte |= 13 << 4;
-                } else {
-                    byte |= 14 << 4;
-                }
-                if value.len() <= 12 {
-                    byte |= value.len() as u8;
-                } else if value.len() < 269 {
-                    byte |= 13;
-                } else {
-                    byte |= 14;
-                }
-                let _ = header.push(byte);
-
-                if delta > 12 && delta < 269 {
-                    let _ = header.push((delta - 13) as u8);
-                } else if delta >= 269 {
-                    let fix = delta - 269;
-                    let _ = header.push((fix >> 8) as u8);
-                    let _ = header.push((fix & 0xFF) as u8);
-                }
+impl<'a, const N: usize> Packet<'a, N> {
+    /// Decodes the buffer into this packet, reusing its option storage. On
+    /// error only the options have been written, partially.
+    fn decode_into(
+        &mut self,
+        buf: &'a [u8],
+        policy: TokenPolicy,
+        max_option_bytes: usize,
+    ) -> Result<(), DetailedMessageError> {
+        let at_start = |kind| DetailedMessageError { kind, offset: 0 };
+        let raw_header = Packet::try_header(buf).map_err(at_start)?;
+        let (token_start, token_end, options_start) =
+            Packet::token_span(buf, raw_header.0, policy).map_err(at_start)?;
+        let token_nonconformant = token_end < options_start;
 
-                if value.len() > 12 && value.len() < 269 {
-                    let _ = header.push((value.len() - 13) as u8);
-                } else if value.len() >= 269 {
-                    let fix = (value.len() - 269) as u16;
-                    let _ = header.push((fix >> 8) as u8);
-                    let _ = header.push((fix & 0xFF) as u8);
-                }
+        let options = &mut self.options;
+        options.clear();
+        self.payload = Packet::walk_options(buf, options_start, max_option_bytes, |option| {
+            options
+                .push(option)
+                .map_err(|_| MessageError::OptionsLimitExceeded)
+        })?;
+        self.ver_type_tkl = if token_nonconformant {
+            raw_header.0 & 0xF0 | 8
+        } else {
+            raw_header.0
+        };
+        self.code = raw_header.1.into();
+        self.message_id = raw_header.2;
+        self.token = &buf[token_start..token_end];
+        self.source = buf;
+        self.options_start = options_start;
+        self.payload_truncated = false;
+        self.token_nonconformant = token_nonconformant;
+        Ok(())
+    }
 
-                options_delta_length += delta;
-                unsafe {
-                    use core::ptr;
-                    let buf_len = options_bytes.len();
-                    ptr::copy(
-                        header.as_ptr(),
-                        options_bytes.as_mut_ptr().add(buf_len),
-                        header.len(),
-                    );
-                    ptr::copy(
-                        value.as_ptr(),
-                        options_bytes.as_mut_ptr().add(buf_len + header.len()),
-                        value.len(),
-                    );
-                    options_bytes.set_len(buf_len + header.len() + value.len());
-                }
-                j += 1;
-            }
-            i = j;
-        }
+    #[inline]
+    pub fn get_version(&self) -> u8 {
+        self.ver_type_tkl >> 6
+    }
 
-        let mut buf_length = 4 + self.payload.len() + self.token.len();
-        if self.get_code() != MessageClass::Empty && !self.payload.is_empty() {
-            buf_length += 1;
+    #[inline]
+    pub fn get_type(&self) -> MessageType {
+        let tn = (0x30 & self.ver_type_tkl) >> 4;
+        match tn {
+            0 => MessageType::Confirmable,
+            1 => MessageType::NonConfirmable,
+            2 => MessageType::Acknowledgement,
+            3 => MessageType::Reset,
+            _ => unreachable!(),
         }
-        buf_length += options_bytes.len();
+    }
 
-        if PACKET_MAX_SIZE < buf_length {
-            return Err(MessageError::InvalidPacketLength);
+    pub fn set_type(&mut self, t: MessageType) {
+        let tn = match t {
+            MessageType::Confirmable => 0,
+            MessageType::NonConfirmable => 1,
+            MessageType::Acknowledgement => 2,
+            MessageType::Reset => 3,
+        };
+        self.ver_type_tkl = (self.ver_type_tkl & !0x30) | tn << 4;
+    }
+
+    /// Returns how the message is matched to a pending exchange. Empty
+    /// ACKs and Resets match by message ID, piggybacked responses by both
+    /// message ID and token, and Confirmable or Non-confirmable messages by
+    /// token.
+    pub fn correlation_mode(&self) -> CorrelationMode {
+        match self.get_type() {
+            MessageType::Acknowledgement if self.code != MessageClass::Empty => {
+                CorrelationMode::Both
+            }
+            MessageType::Acknowledgement | MessageType::Reset => CorrelationMode::ByMessageId,
+            MessageType::Confirmable | MessageType::NonConfirmable => CorrelationMode::ByToken,
         }
+    }
 
-        let mut buf = Vec::<u8, PACKET_MAX_SIZE>::new();
-        let _ = buf.push(self.ver_type_tkl);
-        let _ = buf.push(self.code.into());
-        let id_bytes = self.message_id.to_be_bytes();
-        buf.extend(id_bytes);
+    /// Returns whether the packet is an empty ACK, which acknowledges a
+    /// Confirmable request whose response is sent separately.
+    pub fn is_empty_ack(&self) -> bool {
+        self.get_type() == MessageType::Acknowledgement
+            && self.code == MessageClass::Empty
+            && self.options.is_empty()
+            && self.payload.is_empty()
+    }
 
-        unsafe {
-            use core::ptr;
-            let buf_len = buf.len();
-            ptr::copy(
-                self.token.as_ptr(),
-                buf.as_mut_ptr().add(buf_len),
-                self.token.len(),
-            );
-            ptr::copy(
-                options_bytes.as_ptr(),
-                buf.as_mut_ptr().add(buf_len + self.token.len()),
-                options_bytes.len(),
-            );
-            buf.set_len(buf_len + self.token.len() + options_bytes.len());
-        }
+    /// Returns a copy of the packet sent as Non-confirmable, e.g. to resend
+    /// a failed Confirmable request.
+    pub fn to_non_confirmable(&self) -> Packet<'a, N> {
+        let mut packet = self.clone();
+        packet.set_type(MessageType::NonConfirmable);
+        packet
+    }
 
-        if self.get_code() != MessageClass::Empty && !self.payload.is_empty() {
-            let _ = buf.push(0xFF);
-            unsafe {
-                use core::ptr;
-                let buf_len = buf.len();
-                ptr::copy(
-                    self.payload.as_ptr(),
-                    buf.as_mut_ptr().add(buf.len()),
-                    self.payload.len(),
-                );
-                buf.set_len(buf_len + self.payload.len());
+    /// Returns the TKL field, which is the token length for tokens of up
+    /// to 12 bytes. Use `get_token().len()` for RFC 8974 extended tokens.
+    #[inline]
+    pub fn get_token_length(&self) -> u8 {
+        Self::get_token_length_internal(self.ver_type_tkl)
+    }
+
+    fn get_token_length_internal(ver_type_tkl: u8) -> u8 {
+        0x0F & ver_type_tkl
+    }
+
+    /// Returns the raw version, type and token length byte.
+    #[inline]
+    pub fn raw_header_byte(&self) -> u8 {
+        self.ver_type_tkl
+    }
+
+    /// Returns the raw code byte.
+    #[inline]
+    pub fn code_byte(&self) -> u8 {
+        self.code.into()
+    }
+
+    #[inline]
+    pub fn get_message_id(&self) -> u16 {
+        self.message_id
+    }
+
+    /// Returns the message ID and token, which together identify the
+    /// exchange.
+    pub fn transaction_key(&self) -> (u16, &'a [u8]) {
+        (self.message_id, self.token)
+    }
+
+    pub fn options(&self) -> core::slice::Iter<'_, OptionPair<'a>> {
+        self.options.iter()
+    }
+
+    /// Returns the options as a slice sorted by option number, e.g. for
+    /// indexing or `partition_point`.
+    pub fn options_slice(&self) -> &[OptionPair<'a>] {
+        &self.options
+    }
+
+    /// Returns the options paired with their typed option number.
+    pub fn typed_options(&self) -> impl Iterator<Item = (CoapOption, &[u8])> {
+        self.options
+            .iter()
+            .map(|p| (CoapOption::from(p.num), p.value))
+    }
+
+    /// Returns the options with their values decoded according to
+    /// `CoapOption::value_format`, for logging.
+    pub fn decoded_options(&self) -> impl Iterator<Item = (CoapOption, DecodedValue<'_>)> {
+        self.typed_options().map(|(tp, value)| {
+            let decoded = match tp.value_format() {
+                OptionValueFormat::Empty if value.is_empty() => Some(DecodedValue::Empty),
+                OptionValueFormat::Uint => self.to_uint::<u64>(value).ok().map(DecodedValue::Uint),
+                OptionValueFormat::String => {
+                    core::str::from_utf8(value).ok().map(DecodedValue::Str)
+                }
+                _ => None,
+            };
+            (tp, decoded.unwrap_or(DecodedValue::Opaque(value)))
+        })
+    }
+
+    /// Concatenates the values of all options of the given type, separated
+    /// by `sep`. Returns BufferTooSmall if the result doesn't fit M bytes.
+    pub fn join_option_values<const M: usize>(
+        &self,
+        tp: CoapOption,
+        sep: u8,
+    ) -> Result<Vec<u8, M>, MessageError> {
+        let mut joined = Vec::new();
+        for (i, option) in self.get_options(tp).enumerate() {
+            if i > 0 {
+                joined.push(sep).map_err(|_| MessageError::BufferTooSmall)?;
             }
+            joined
+                .extend_from_slice(option.value)
+                .map_err(|_| MessageError::BufferTooSmall)?;
         }
-        Ok(buf)
+        Ok(joined)
     }
 
-    fn to_uint<T>(&self, encoded: &[u8]) -> Result<T, IncompatibleOptionValueFormat>
-    where
-        T: TryFrom<u64>
-            + From<u8>
-            + core::ops::Shl<usize, Output = T>
-            + core::ops::Add<Output = T>
-            + Default,
-    {
-        let value_size = size_of::<T>();
-        if encoded.len() > value_size {
-            let mut s = String::<50>::new();
-            match write!(
-                s,
-                "overflow: got {} bytes, expected {}",
-                encoded.len(),
-                value_size
-            ) {
-                Err(_) => return Err(IncompatibleOptionValueFormat { message: s }),
-                _ => {}
+    /// Returns the start and end offsets of the option at `index`, header
+    /// included, within the buffer the packet was decoded from. Returns
+    /// None for built packets and if the options up to `index` were changed
+    /// after decoding.
+    pub fn option_byte_range(&self, index: usize) -> Option<(usize, usize)> {
+        if self.source.is_empty() || index >= self.options.len() {
+            return None;
+        }
+        let mut start = self.options_start;
+        let mut number = 0;
+        for (i, expected) in self.options[..=index].iter().enumerate() {
+            let (option, end) = Packet::read_option(self.source, start, number).ok()??;
+            if option.num != expected.num || !core::ptr::eq(option.value, expected.value) {
+                return None;
+            }
+            if i == index {
+                return Some((start, end));
             }
+            number = option.num;
+            start = end;
         }
-        Ok(encoded
-            .iter()
-            .fold(T::default(), |acc, &b| (acc << 8) + T::from(b)))
+        None
     }
-}
 
-#[cfg(test)]
-mod test {
-    use super::*;
+    /// Returns the start and end offsets of the payload within the buffer
+    /// the packet was decoded from. Returns None for built packets, packets
+    /// without a payload and payloads that were replaced after decoding.
+    pub fn payload_byte_range(&self) -> Option<(usize, usize)> {
+        if self.payload.is_empty() {
+            return None;
+        }
+        let start = self.source.len().checked_sub(self.payload.len())?;
+        if !core::ptr::eq(self.payload, &self.source[start..]) {
+            return None;
+        }
+        Some((start, self.source.len()))
+    }
 
-    #[test]
-    fn test_header_codes() {
-        for code in 0..255 {
-            let p = Packet::new(
-                MessageType::Confirmable,
-                code.into(),
-                /* version= */ 1,
-                /* message_id= */ 1,
-                /* token= */ &[],
-                /* options= */ &mut Vec::new(),
-                /* payload= */ &[],
-            );
-            let class: MessageClass = code.into();
-            // valid items
-            if !matches!(class, MessageClass::Reserved(_)) {
-                assert_eq!(u8::from(class), code);
-                assert_eq!(class, p.get_code());
+    pub fn get_code(&self) -> MessageClass {
+        self.code
+    }
+
+    /// Returns whether the packet was decoded from an incomplete buffer by
+    /// `from_bytes_with_truncation`, so the payload is cut short.
+    pub fn is_payload_truncated(&self) -> bool {
+        self.payload_truncated
+    }
+
+    /// Returns whether `from_bytes_lenient` clamped an invalid token length
+    /// to 8 bytes.
+    pub fn is_token_nonconformant(&self) -> bool {
+        self.token_nonconformant
+    }
+
+    pub fn get_token(&self) -> &'a [u8] {
+        self.token
+    }
+
+    /// Returns whether the packet carries a token, i.e. TKL is non-zero.
+    /// A zero-length token is the same as no token (RFC 7252, section 5.3.1).
+    pub fn has_token(&self) -> bool {
+        self.get_token_length() > 0
+    }
+
+    /// Compares code, type, options and payload, ignoring the message ID
+    /// and token.
+    pub fn semantically_eq(&self, other: &Packet<'_, N>) -> bool {
+        self.code == other.code
+            && self.get_type() == other.get_type()
+            && self.options == other.options
+            && self.payload == other.payload
+    }
+
+    /// Compares the options regardless of the order of distinct option
+    /// numbers, while the order of repeated options must match.
+    pub fn options_eq<const M: usize>(&self, other: &Packet<'_, M>) -> bool {
+        self.options.len() == other.options.len()
+            && self.options.iter().all(|option| {
+                let same_num = |p: &&OptionPair<'_>| p.num == option.num;
+                self.options
+                    .iter()
+                    .filter(same_num)
+                    .map(|p| p.value)
+                    .eq(other.options.iter().filter(same_num).map(|p| p.value))
+            })
+    }
+
+    /// Compares the options of two packets with sorted options, returning
+    /// the options only `other` has, the options only this packet has and
+    /// the options whose values differ, in that order.
+    pub fn option_diff(
+        &self,
+        other: &Packet<'_, N>,
+    ) -> (Vec<CoapOption, N>, Vec<CoapOption, N>, Vec<CoapOption, N>) {
+        let (mut added, mut removed, mut changed) = (Vec::new(), Vec::new(), Vec::new());
+        let (ours, theirs) = (self.options.as_slice(), other.options.as_slice());
+        let (mut i, mut j) = (0, 0);
+        while i < ours.len() || j < theirs.len() {
+            let num = match (ours.get(i), theirs.get(j)) {
+                (Some(a), Some(b)) => a.num.min(b.num),
+                (Some(a), None) => a.num,
+                (None, Some(b)) => b.num,
+                (None, None) => break,
+            };
+            let ours_len = ours[i..].iter().take_while(|p| p.num == num).count();
+            let theirs_len = theirs[j..].iter().take_while(|p| p.num == num).count();
+            let target = if ours_len == 0 {
+                Some(&mut added)
+            } else if theirs_len == 0 {
+                Some(&mut removed)
+            } else if !ours[i..i + ours_len]
+                .iter()
+                .map(|p| p.value)
+                .eq(theirs[j..j + theirs_len].iter().map(|p| p.value))
+            {
+                Some(&mut changed)
+            } else {
+                None
+            };
+            if let Some(target) = target {
+                // Each list holds distinct numbers of one packet, at most N.
+                let _ = target.push(CoapOption::from(num));
             }
+            i += ours_len;
+            j += theirs_len;
         }
+        (added, removed, changed)
     }
 
-    #[test]
-    fn from_bytes_fail() {
-        let b: &[u8] = &[1, 2, 3];
-        let p = Packet::from_bytes(b);
-        assert_eq!(MessageError::InvalidPacketLength, p.unwrap_err());
+    /// Returns the value of the signaling option with the given number if
+    /// the packet is the given signaling message. Signaling option numbers
+    /// are specific to each signaling code.
+    fn signaling_option(&self, signal: SignalType, num: u16) -> Option<&'a [u8]> {
+        if self.code != MessageClass::Signaling(signal) {
+            return None;
+        }
+        self.options.iter().find(|p| p.num == num).map(|p| p.value)
     }
 
-    #[test]
-    fn types() {
-        let p_acked = Packet::new(
-            MessageType::Acknowledgement,
-            MessageClass::Request(RequestType::Get),
-            /* version= */ 1,
-            /* message_id= */ 1,
-            /* token= */ &[],
-            /* options= */ &mut Vec::new(),
-            /* payload= */ &[],
-        );
-        assert_eq!(MessageType::Acknowledgement, p_acked.get_type());
-        let p_confirmed = Packet::new(
-            MessageType::Confirmable,
-            MessageClass::Request(RequestType::Get),
-            /* version= */ 1,
-            /* message_id= */ 1,
-            /* token= */ &[],
-            /* options= */ &mut Vec::new(),
-            /* payload= */ &[],
+    /// Returns the Max-Message-Size option of a CSM message.
+    pub fn csm_max_message_size(&self) -> Option<u32> {
+        self.signaling_option(SignalType::Csm, 2)
+            .and_then(|value| self.to_uint(value).ok())
+    }
+
+    /// Returns whether a CSM message carries the Block-Wise-Transfer option.
+    pub fn csm_block_wise_transfer(&self) -> bool {
+        self.signaling_option(SignalType::Csm, 4).is_some()
+    }
+
+    /// Returns whether a Ping or Pong message carries the Custody option.
+    pub fn custody(&self) -> bool {
+        self.signaling_option(SignalType::Ping, 2).is_some()
+            || self.signaling_option(SignalType::Pong, 2).is_some()
+    }
+
+    /// Returns the Alternative-Address option of a Release message.
+    pub fn release_alternative_address(&self) -> Option<&'a str> {
+        self.signaling_option(SignalType::Release, 2)
+            .and_then(|value| core::str::from_utf8(value).ok())
+    }
+
+    /// Returns the Hold-Off option of a Release message, in seconds.
+    pub fn release_hold_off(&self) -> Option<u32> {
+        self.signaling_option(SignalType::Release, 4)
+            .and_then(|value| self.to_uint(value).ok())
+    }
+
+    /// Returns the Bad-CSM-Option option of an Abort message.
+    pub fn abort_bad_csm_option(&self) -> Option<u16> {
+        self.signaling_option(SignalType::Abort, 2)
+            .and_then(|value| self.to_uint(value).ok())
+    }
+
+    /// Returns whether the packet token equals the given one. An empty
+    /// token only matches a packet without a token.
+    pub fn token_matches(&self, token: &[u8]) -> bool {
+        self.token == token
+    }
+
+    pub fn get_payload(&self) -> &'a [u8] {
+        self.payload
+    }
+
+    /// Returns whether the payload is encoded, i.e. it is non-empty and
+    /// the message isn't an Empty message. An empty payload is encoded
+    /// without the payload marker (RFC 7252, section 3).
+    pub fn has_payload(&self) -> bool {
+        self.code != MessageClass::Empty && !self.payload.is_empty()
+    }
+
+    pub fn get_options(&self, tp: CoapOption) -> impl Iterator<Item = &OptionPair<'a>> {
+        self.options.iter().filter(move |&p| p.num == tp.into())
+    }
+
+    /// Returns whether an empty If-Match option is present, which makes the
+    /// request conditional on any current representation existing (RFC 7252,
+    /// section 5.10.8.1).
+    pub fn if_match_any(&self) -> bool {
+        self.get_options(CoapOption::IfMatch)
+            .any(|option| option.value.is_empty())
+    }
+
+    /// Returns the ETags of the If-Match options, leaving out the empty
+    /// match-any value.
+    pub fn if_match_etags(&self) -> impl Iterator<Item = &'a [u8]> + '_ {
+        self.get_options(CoapOption::IfMatch)
+            .map(|option| option.value)
+            .filter(|value| !value.is_empty())
+    }
+
+    /// Returns the ETag values, with InvalidOptionLength for any ETag that
+    /// isn't 1 to 8 bytes long.
+    pub fn get_etags_checked(&self) -> impl Iterator<Item = Result<&'a [u8], MessageError>> + '_ {
+        self.get_options(CoapOption::ETag).map(|option| {
+            if (1..=8).contains(&option.value.len()) {
+                Ok(option.value)
+            } else {
+                Err(MessageError::InvalidOptionLength)
+            }
+        })
+    }
+
+    /// Writes the values of the options of the given type into `out`,
+    /// returning the number written. Values beyond the array are skipped.
+    pub fn collect_options<const M: usize>(
+        &self,
+        tp: CoapOption,
+        out: &mut [&'a [u8]; M],
+    ) -> usize {
+        let mut count = 0;
+        for (slot, option) in out.iter_mut().zip(self.get_options(tp)) {
+            *slot = option.value;
+            count += 1;
+        }
+        count
+    }
+
+    /// Returns the options with numbers from `lo` to `hi`, inclusive.
+    pub fn options_in_range(&self, lo: u16, hi: u16) -> impl Iterator<Item = &OptionPair<'a>> {
+        let start = self.options.partition_point(|p| p.num < lo);
+        let end = self.options.partition_point(|p| p.num <= hi).max(start);
+        self.options[start..end].iter()
+    }
+
+    pub fn get_first_option(&self, tp: CoapOption) -> Option<&OptionPair<'a>> {
+        self.options.iter().find(|&p| p.num == tp.into())
+    }
+
+    /// Returns whether the option is present. This is the accessor for
+    /// empty-format options such as If-None-Match, which carry no value.
+    pub fn has_option(&self, tp: CoapOption) -> bool {
+        self.get_first_option(tp).is_some()
+    }
+
+    /// Copies the value of the first matching option into `out`, returning
+    /// the number of bytes written, or None if the option is absent.
+    pub fn copy_option_value(
+        &self,
+        tp: CoapOption,
+        out: &mut [u8],
+    ) -> Option<Result<usize, MessageError>> {
+        self.get_first_option(tp).map(|option| {
+            let len = option.value.len();
+            match out.get_mut(..len) {
+                Some(dst) => {
+                    dst.copy_from_slice(option.value);
+                    Ok(len)
+                }
+                None => Err(MessageError::BufferTooSmall),
+            }
+        })
+    }
+
+    /// Returns the number of the first critical option not listed in
+    /// `known`, which the server should report with 4.02 Bad Option.
+    pub fn first_unrecognized_critical(&self, known: &[CoapOption]) -> Option<u16> {
+        self.options
+            .iter()
+            .map(|p| CoapOption::from(p.num))
+            .find(|option| option.is_critical() && !known.contains(option))
+            .map(u16::from)
+    }
+
+    /// Returns the value of the first option with the given number.
+    pub fn get_custom_option(&self, num: u16) -> Option<&[u8]> {
+        self.options.iter().find(|p| p.num == num).map(|p| p.value)
+    }
+
+    /// Replaces all options with the given number by a single option.
+    pub fn set_custom_option(&mut self, num: u16, value: &'a [u8]) -> Result<(), MessageError> {
+        self.set_option_value(num, value)
+    }
+
+    /// Restores the ascending option order after `options` was modified
+    /// directly. Options with the same number keep their relative order.
+    pub fn resort_options(&mut self) {
+        Packet::sort_option_pairs(&mut self.options);
+    }
+
+    /// Removes all options of the given type, returning how many were
+    /// removed.
+    pub fn remove_options(&mut self, tp: CoapOption) -> usize {
+        let num = u16::from(tp);
+        let len = self.options.len();
+        self.options.retain(|p| p.num != num);
+        len - self.options.len()
+    }
+
+    /// Replaces all options with the given number, keeping the options
+    /// sorted.
+    fn set_option_value(&mut self, num: u16, value: &'a [u8]) -> Result<(), MessageError> {
+        self.options.retain(|p| p.num != num);
+        let index = self.options.partition_point(|p| p.num < num);
+        self.options
+            .insert(index, OptionPair { num, value })
+            .map_err(|_| MessageError::OptionsLimitExceeded)
+    }
+
+    /// Returns the Content-Format number. An empty value decodes to 0
+    /// (text/plain), as zero-length is the canonical encoding of 0.
+    pub fn get_content_format_value(&self) -> Option<u16> {
+        self.get_first_option(CoapOption::ContentFormat)
+            .map(|option| self.to_uint::<u16>(option.value))
+            .and_then(|value| value.ok())
+    }
+
+    /// Returns the Content-Format, or None if the option is absent or
+    /// holds an unknown format.
+    pub fn get_content_format(&self) -> Option<ContentFormat> {
+        self.get_content_format_value()
+            .and_then(|value| ContentFormat::try_from(value as usize).ok())
+    }
+
+    /// Returns the declared Content-Format together with the payload.
+    pub fn payload_with_format(&self) -> (Option<ContentFormat>, &'a [u8]) {
+        (self.get_content_format(), self.payload)
+    }
+
+    /// Guesses the payload format from its leading bytes, for debugging
+    /// peers that omit the Content-Format option. Returns None if the
+    /// option is present or the payload isn't recognized.
+    pub fn guess_content_format(&self) -> Option<ContentFormat> {
+        if self.has_option(CoapOption::ContentFormat) {
+            return None;
+        }
+        let payload = self.payload;
+        if payload.starts_with(&[0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A]) {
+            return Some(ContentFormat::ImagePng);
+        }
+        if payload.starts_with(b"GIF87a") || payload.starts_with(b"GIF89a") {
+            return Some(ContentFormat::ImageGif);
+        }
+        if payload.starts_with(&[0xFF, 0xD8, 0xFF]) {
+            return Some(ContentFormat::ImageJpeg);
+        }
+        // Self-described CBOR tag, or a CBOR array/map that fits the payload.
+        if payload.starts_with(&[0xD9, 0xD9, 0xF7]) || Self::is_cbor_container(payload) {
+            return Some(ContentFormat::ApplicationCBOR);
+        }
+        match payload.iter().find(|b| !b.is_ascii_whitespace()) {
+            Some(b'{') | Some(b'[') => Some(ContentFormat::ApplicationJSON),
+            Some(b'<') => Some(ContentFormat::ApplicationXML),
+            _ => None,
+        }
+    }
+
+    /// Returns whether the payload starts with a CBOR array or map header
+    /// whose declared number of items can fit the rest of the payload, each
+    /// item taking at least one byte.
+    fn is_cbor_container(payload: &[u8]) -> bool {
+        let Some(&initial) = payload.first() else {
+            return false;
+        };
+        let bytes_per_entry = match initial >> 5 {
+            4 => 1,
+            5 => 2,
+            _ => return false,
+        };
+        let (header_len, count) = match initial & 0x1F {
+            count @ 0..=23 => (1, count as u64),
+            24..=27 => {
+                let header_len = 1 + (1 << ((initial & 0x1F) - 24));
+                let Some(argument) = payload.get(1..header_len) else {
+                    return false;
+                };
+                let count = argument.iter().fold(0u64, |acc, &b| acc << 8 | b as u64);
+                (header_len, count)
+            }
+            // Indefinite length, terminated by a break byte.
+            31 => return payload.len() > 1 && payload.ends_with(&[0xFF]),
+            _ => return false,
+        };
+        let rest = (payload.len() - header_len) as u64;
+        if count == 0 {
+            return rest == 0;
+        }
+        count
+            .checked_mul(bytes_per_entry)
+            .is_some_and(|needed| needed <= rest)
+    }
+
+    /// Returns whether the payload is declared as SenML/SenSML.
+    pub fn is_senml(&self) -> bool {
+        self.get_content_format()
+            .is_some_and(|format| format.is_senml())
+    }
+
+    /// Returns whether the payload is declared as a CBOR based format.
+    pub fn is_cbor(&self) -> bool {
+        self.get_content_format()
+            .is_some_and(|format| format.is_cbor())
+    }
+
+    /// Returns whether the payload is declared as a JSON based format.
+    pub fn is_json(&self) -> bool {
+        self.get_content_format()
+            .is_some_and(|format| format.is_json())
+    }
+
+    /// Returns whether the payload is declared as application/multipart-core
+    /// (RFC 8710). Each part carries its own Content-Format inside the
+    /// CBOR payload, which callers must decode themselves.
+    pub fn is_multipart(&self) -> bool {
+        self.get_content_format() == Some(ContentFormat::ApplicationMultipartCore)
+    }
+
+    /// Returns the Observe value. An empty value decodes to 0.
+    pub fn get_observe_value(&self) -> Option<u32> {
+        self.get_first_option(CoapOption::Observe)
+            .map(|option| self.to_uint::<u32>(option.value))
+            .and_then(|value| value.ok())
+    }
+
+    /// Same as `get_observe_value`, but returns InvalidOptionLength for
+    /// values longer than the 3 bytes of a 24-bit sequence number.
+    pub fn get_observe_value_checked(&self) -> Option<Result<u32, MessageError>> {
+        self.get_first_option(CoapOption::Observe).map(|option| {
+            if option.value.len() > 3 {
+                return Err(MessageError::InvalidOptionLength);
+            }
+            Ok(option
+                .value
+                .iter()
+                .fold(0, |acc, &b| acc << 8 | u32::from(b)))
+        })
+    }
+
+    /// Sets the Observe option to the low 24 bits of `seq`, minimally
+    /// encoded into `buf`.
+    pub fn set_observe_seq(&mut self, seq: u32, buf: &'a mut [u8; 3]) -> Result<(), MessageError> {
+        let value = encode_uint((seq & 0xFF_FFFF).into(), buf)?;
+        self.set_option_value(CoapOption::Observe.into(), value)
+    }
+
+    /// Decodes the Block1 option, if present.
+    pub fn get_block1(&self) -> Option<Result<BlockValue, InvalidBlockValue>> {
+        self.get_first_option(CoapOption::Block1)
+            .map(|option| BlockValue::try_from(option.value))
+    }
+
+    /// Decodes the Block2 option, if present.
+    pub fn get_block2(&self) -> Option<Result<BlockValue, InvalidBlockValue>> {
+        self.get_first_option(CoapOption::Block2)
+            .map(|option| BlockValue::try_from(option.value))
+    }
+
+    /// Encodes `value` into `buf` and sets it as the Block1 option,
+    /// replacing any existing one.
+    pub fn set_block1(
+        &mut self,
+        value: BlockValue,
+        buf: &'a mut [u8; 3],
+    ) -> Result<(), MessageError> {
+        self.set_option_value(CoapOption::Block1.into(), value.encode(buf))
+    }
+
+    /// Encodes `value` into `buf` and sets it as the Block2 option,
+    /// replacing any existing one.
+    pub fn set_block2(
+        &mut self,
+        value: BlockValue,
+        buf: &'a mut [u8; 3],
+    ) -> Result<(), MessageError> {
+        self.set_option_value(CoapOption::Block2.into(), value.encode(buf))
+    }
+
+    /// Decodes the first matching option as a two's complement signed
+    /// integer of up to 4 bytes. This is non-standard for the registered
+    /// options and intended for vendor options (`CoapOption::Unknown`).
+    pub fn option_i32(&self, tp: CoapOption) -> Option<i32> {
+        let value = self.get_first_option(tp)?.value;
+        if value.len() > 4 {
+            return None;
+        }
+        let negative = value.first().is_some_and(|b| b & 0x80 != 0);
+        let mut bytes = if negative { [0xFF; 4] } else { [0; 4] };
+        bytes[4 - value.len()..].copy_from_slice(value);
+        Some(i32::from_be_bytes(bytes))
+    }
+
+    /// Decodes the first matching option as an 8-bit unsigned integer. As
+    /// in the other uint accessors, an empty value decodes to 0.
+    pub fn option_u8(&self, tp: CoapOption) -> Option<Result<u8, IncompatibleOptionValueFormat>> {
+        self.get_first_option(tp)
+            .map(|option| self.to_uint(option.value))
+    }
+
+    /// Decodes the first matching option as a 16-bit unsigned integer.
+    pub fn option_u16(&self, tp: CoapOption) -> Option<Result<u16, IncompatibleOptionValueFormat>> {
+        self.get_first_option(tp)
+            .map(|option| self.to_uint(option.value))
+    }
+
+    /// Decodes the first matching option as a 32-bit unsigned integer.
+    pub fn option_u32(&self, tp: CoapOption) -> Option<Result<u32, IncompatibleOptionValueFormat>> {
+        self.get_first_option(tp)
+            .map(|option| self.to_uint(option.value))
+    }
+
+    /// Decodes the first matching option as a 64-bit unsigned integer.
+    pub fn option_u64(&self, tp: CoapOption) -> Option<Result<u64, IncompatibleOptionValueFormat>> {
+        self.get_first_option(tp)
+            .map(|option| self.to_uint(option.value))
+    }
+
+    /// Returns a FNV-1a hash over the method and the options forming the
+    /// cache key (RFC 7252, section 5.6). NoCacheKey options, the
+    /// conditional request options and Observe (RFC 7641, section 2) are
+    /// excluded.
+    pub fn cache_key_hash(&self) -> u32 {
+        const FNV_OFFSET_BASIS: u32 = 0x811C9DC5;
+        const FNV_PRIME: u32 = 0x01000193;
+        fn fnv1a(hash: u32, bytes: &[u8]) -> u32 {
+            bytes.iter().fold(hash, |hash, &b| {
+                (hash ^ u32::from(b)).wrapping_mul(FNV_PRIME)
+            })
+        }
+
+        let mut hash = fnv1a(FNV_OFFSET_BASIS, &[self.code.into()]);
+        for option in self.options() {
+            let tp = CoapOption::from(option.num);
+            if tp.is_no_cache_key()
+                || matches!(
+                    tp,
+                    CoapOption::ETag
+                        | CoapOption::IfMatch
+                        | CoapOption::IfNoneMatch
+                        | CoapOption::Observe
+                )
+            {
+                continue;
+            }
+            hash = fnv1a(hash, &option.num.to_be_bytes());
+            hash = fnv1a(hash, &(option.value.len() as u16).to_be_bytes());
+            hash = fnv1a(hash, option.value);
+        }
+        hash
+    }
+
+    /// Brings the packet into a canonical form for cache lookups: the
+    /// options are sorted, and NoCacheKey options and Max-Age are removed.
+    pub fn normalize_for_cache(&mut self) {
+        self.options.retain(|p| {
+            let tp = CoapOption::from(p.num);
+            !tp.is_no_cache_key() && tp != CoapOption::MaxAge
+        });
+        self.resort_options();
+    }
+
+    /// Checks the proxy options (RFC 7252, section 5.10.2): Proxy-Uri
+    /// excludes the Uri-Host, Uri-Port, Uri-Path and Uri-Query options,
+    /// while Proxy-Scheme needs the Uri-Host option to build the URI.
+    pub fn validate_proxy_options(&self) -> Result<(), MessageError> {
+        let has = |tp: CoapOption| self.has_option(tp);
+        if has(CoapOption::ProxyUri) {
+            if has(CoapOption::UriHost)
+                || has(CoapOption::UriPort)
+                || has(CoapOption::UriPath)
+                || has(CoapOption::UriQuery)
+            {
+                return Err(MessageError::ProxyUriWithUriOptions);
+            }
+        } else if has(CoapOption::ProxyScheme) && !has(CoapOption::UriHost) {
+            return Err(MessageError::ProxySchemeWithoutUriHost);
+        }
+        Ok(())
+    }
+
+    /// Returns the No-Response option bitmask (RFC 7967).
+    pub fn get_no_response_value(&self) -> Option<u8> {
+        self.option_u8(CoapOption::NoResponse)
+            .and_then(|value| value.ok())
+    }
+
+    /// Returns whether the No-Response option asks the server not to send
+    /// a response with the given code.
+    pub fn should_suppress_response(&self, response_code: ResponseType) -> bool {
+        let mask = match u8::from(MessageClass::Response(response_code)) >> 5 {
+            2 => 0x02,
+            4 => 0x08,
+            5 => 0x10,
+            _ => return false,
+        };
+        self.get_no_response_value()
+            .is_some_and(|value| value & mask != 0)
+    }
+
+    /// Checks that non-repeatable options occur at most once, uint options
+    /// fit their registered length and the packet fits PACKET_MAX_SIZE.
+    pub fn validate_for_send(&self) -> Result<(), MessageError> {
+        for (i, option) in self.options.iter().enumerate() {
+            let tp = CoapOption::from(option.num);
+            if !tp.is_repeatable() && self.options[..i].iter().any(|p| p.num == option.num) {
+                return Err(MessageError::DuplicateOption);
+            }
+            if tp
+                .max_uint_length()
+                .is_some_and(|max| option.value.len() > max)
+            {
+                return Err(MessageError::InvalidOptionLength);
+            }
+        }
+        if self.encoded_len()? > PACKET_MAX_SIZE {
+            return Err(MessageError::InvalidPacketLength);
+        }
+        Ok(())
+    }
+
+    /// Same as `to_bytes`, but runs `validate_for_send` first.
+    pub fn to_bytes_checked(&self) -> Result<Vec<u8, PACKET_MAX_SIZE>, MessageError> {
+        self.validate_for_send()?;
+        self.to_bytes()
+    }
+
+    /// Returns every issue a strict peer could reject the packet for when
+    /// sent over UDP: a version other than 1, a reserved code class or a
+    /// signaling code, duplicate non-repeatable options, uint values with
+    /// leading zero bytes and unrecognized critical options. Each kind of
+    /// issue is reported once.
+    pub fn strict_conformance_report(&self) -> Vec<MessageError, 8> {
+        let mut report = Vec::new();
+        let mut add = |err: MessageError| {
+            if !report.contains(&err) {
+                // The report has room for every kind of issue.
+                let _ = report.push(err);
+            }
+        };
+        if self.get_version() != 1
+            || matches!(
+                self.get_code(),
+                MessageClass::Reserved(_) | MessageClass::Signaling(_)
+            )
+        {
+            add(MessageError::InvalidHeader);
+        }
+        for (i, option) in self.options.iter().enumerate() {
+            let tp = CoapOption::from(option.num);
+            if !tp.is_repeatable() && self.options[..i].iter().any(|p| p.num == option.num) {
+                add(MessageError::DuplicateOption);
+            }
+            if tp.value_format() == OptionValueFormat::Uint && option.value.first() == Some(&0) {
+                add(MessageError::NonMinimalUint);
+            }
+            if matches!(tp, CoapOption::Unknown(_)) && tp.is_critical() {
+                add(MessageError::UnknownCriticalOption);
+            }
+        }
+        report
+    }
+
+    /// Encodes the packet. Option deltas and lengths always use the
+    /// shortest extended form, so the output is deterministic. The options
+    /// must be sorted, see `resort_options`, otherwise InvalidOptionDelta is
+    /// returned. Returns InvalidTokenLength for tokens longer than 8 bytes,
+    /// see `to_bytes_extended_token`.
+    pub fn to_bytes(&self) -> Result<Vec<u8, PACKET_MAX_SIZE>, MessageError> {
+        self.encode(false)
+    }
+
+    /// Same as `to_bytes`, but encodes tokens longer than 8 bytes with the
+    /// extended token length of RFC 8974.
+    pub fn to_bytes_extended_token(&self) -> Result<Vec<u8, PACKET_MAX_SIZE>, MessageError> {
+        self.encode(true)
+    }
+
+    fn encode(&self, extended_token: bool) -> Result<Vec<u8, PACKET_MAX_SIZE>, MessageError> {
+        self.check_encodable(extended_token)?;
+        let mut options_delta_length = 0;
+        let mut options_bytes: Vec<u8, PACKET_MAX_SIZE> = Vec::new();
+        let mut i = 0;
+        while i < self.options.len() {
+            let start_option_pair = self.options.get(i);
+            let mut j = i;
+            while j < self.options.len()
+                && start_option_pair.unwrap().num == self.options.get(j).unwrap().num
+            {
+                let value = self.options.get(j).unwrap().value;
+                let delta = start_option_pair
+                    .unwrap()
+                    .num
+                    .checked_sub(options_delta_length)
+                    .ok_or(MessageError::InvalidOptionDelta)?;
+                let header = Self::encode_option_header(delta, value.len())?;
+
+                options_delta_length += delta;
+                if options_bytes.extend_from_slice(&header).is_err()
+                    || options_bytes.extend_from_slice(value).is_err()
+                {
+                    return Err(MessageError::InvalidPacketLength);
+                }
+                j += 1;
+            }
+            i = j;
+        }
+
+        let token_extension = self.token_length_extension();
+        let mut buf_length = 4 + token_extension.len() + self.payload.len() + self.token.len();
+        if self.has_payload() {
+            buf_length += 1;
+        }
+        buf_length += options_bytes.len();
+
+        if PACKET_MAX_SIZE < buf_length {
+            return Err(MessageError::InvalidPacketLength);
+        }
+
+        let mut buf = Vec::<u8, PACKET_MAX_SIZE>::new();
+        let _ = buf.push(self.ver_type_tkl);
+        let _ = buf.push(self.code.into());
+        let id_bytes = self.message_id.to_be_bytes();
+        buf.extend(id_bytes);
+        buf.extend(token_extension);
+
+        unsafe {
+            use core::ptr;
+            let buf_len = buf.len();
+            ptr::copy(
+                self.token.as_ptr(),
+                buf.as_mut_ptr().add(buf_len),
+                self.token.len(),
+            );
+            ptr::copy(
+                options_bytes.as_ptr(),
+                buf.as_mut_ptr().add(buf_len + self.token.len()),
+                options_bytes.len(),
+            );
+            buf.set_len(buf_len + self.token.len() + options_bytes.len());
+        }
+
+        if self.has_payload() {
+            let _ = buf.push(0xFF);
+            unsafe {
+                use core::ptr;
+                let buf_len = buf.len();
+                ptr::copy(
+                    self.payload.as_ptr(),
+                    buf.as_mut_ptr().add(buf.len()),
+                    self.payload.len(),
+                );
+                buf.set_len(buf_len + self.payload.len());
+            }
+        }
+        Ok(buf)
+    }
+
+    /// Writes a human readable dump of the packet to the given sink.
+    pub fn fmt_pretty<W: Write>(&self, w: &mut W) -> core::fmt::Result {
+        let code = u8::from(self.code);
+        writeln!(w, "Version: {}", self.get_version())?;
+        writeln!(w, "Type: {:?}", self.get_type())?;
+        writeln!(w, "Code: {}.{:02} {:?}", code >> 5, code & 0x1F, self.code)?;
+        writeln!(w, "Message ID: {}", self.message_id)?;
+        write!(w, "Token: ")?;
+        Self::fmt_hex(w, self.token)?;
+        writeln!(w)?;
+        for option in self.options() {
+            write!(w, "Option {:?}: ", CoapOption::from(option.num))?;
+            Self::fmt_hex(w, option.value)?;
+            writeln!(w)?;
+        }
+        writeln!(w, "Payload: {} bytes", self.payload.len())
+    }
+
+    /// Returns the number of bytes the options take on the wire. Like
+    /// `to_bytes`, returns InvalidOptionDelta if the options aren't sorted.
+    pub fn options_encoded_len(&self) -> Result<usize, MessageError> {
+        Self::encoded_options_len(self.options())
+    }
+
+    fn encoded_options_len<'o>(
+        options: impl Iterator<Item = &'o OptionPair<'o>>,
+    ) -> Result<usize, MessageError> {
+        let mut previous = 0;
+        let mut len = 0;
+        for option in options {
+            let delta = option
+                .num
+                .checked_sub(previous)
+                .ok_or(MessageError::InvalidOptionDelta)?;
+            len +=
+                Self::encode_option_header(delta, option.value.len())?.len() + option.value.len();
+            previous = option.num;
+        }
+        Ok(len)
+    }
+
+    /// Returns the length of the encoded packet, or the error of
+    /// `options_encoded_len`.
+    pub fn encoded_len(&self) -> Result<usize, MessageError> {
+        let payload_len = if self.has_payload() {
+            1 + self.payload.len()
+        } else {
+            0
+        };
+        Ok(4 + self.token_length_extension().len()
+            + self.token.len()
+            + self.options_encoded_len()?
+            + payload_len)
+    }
+
+    /// Checks the token length and the code before encoding.
+    fn check_encodable(&self, extended_token: bool) -> Result<(), MessageError> {
+        if !extended_token && self.token.len() > 8 {
+            return Err(MessageError::InvalidTokenLength);
+        }
+        if matches!(
+            self.code,
+            MessageClass::Request(RequestType::UnKnown)
+                | MessageClass::Response(ResponseType::UnKnown)
+        ) {
+            return Err(MessageError::InvalidHeader);
+        }
+        Ok(())
+    }
+
+    /// Encodes the packet as the sequence of non-empty chunks `to_bytes`
+    /// would concatenate, for scatter-gather I/O: the header, the token,
+    /// each option header and value, the payload marker and the payload.
+    /// The generated headers are stored in `headers`. Fails like
+    /// `to_bytes`, except that the packet size isn't limited.
+    pub fn chunks<'s>(
+        &'s self,
+        headers: &'s mut ChunkHeaders<N>,
+    ) -> Result<impl Iterator<Item = &'s [u8]>, MessageError> {
+        const PAYLOAD_MARKER: &[u8] = &[0xFF];
+
+        self.check_encodable(false)?;
+        headers.header.clear();
+        headers.options.clear();
+        let id_bytes = self.message_id.to_be_bytes();
+        let _ = headers.header.extend_from_slice(&[
+            self.ver_type_tkl,
+            self.code.into(),
+            id_bytes[0],
+            id_bytes[1],
+        ]);
+        let mut previous = 0;
+        for option in self.options.iter() {
+            let delta = option
+                .num
+                .checked_sub(previous)
+                .ok_or(MessageError::InvalidOptionDelta)?;
+            let header = Self::encode_option_header(delta, option.value.len())?;
+            // Holds one header per option, at most N.
+            let _ = headers.options.push(header);
+            previous = option.num;
+        }
+
+        let headers = &*headers;
+        let options = headers
+            .options
+            .iter()
+            .zip(self.options.iter())
+            .flat_map(|(header, option)| [header.as_slice(), option.value]);
+        let payload = self
+            .has_payload()
+            .then_some([PAYLOAD_MARKER, self.payload])
+            .into_iter()
+            .flatten();
+        Ok([headers.header.as_slice(), self.token]
+            .into_iter()
+            .chain(options)
+            .chain(payload)
+            .filter(|chunk| !chunk.is_empty()))
+    }
+
+    /// Returns the RFC 8974 extended token length bytes that follow the
+    /// message ID, empty for tokens of up to 12 bytes.
+    fn token_length_extension(&self) -> Vec<u8, 2> {
+        let len = self.token.len();
+        let mut extension = Vec::new();
+        match self.get_token_length() {
+            13 => {
+                let _ = extension.push((len - 13) as u8);
+            }
+            14 => {
+                let _ = extension.extend_from_slice(&((len - 269) as u16).to_be_bytes());
+            }
+            _ => {}
+        }
+        extension
+    }
+
+    /// Returns whether the encoded packet fits the MTU. Packets that can't
+    /// be encoded don't fit.
+    pub fn fits_mtu(&self, mtu: usize) -> bool {
+        self.encoded_len().is_ok_and(|len| len <= mtu)
+    }
+
+    /// Returns the largest block size exponent (SZX) for which a block of
+    /// payload fits the MTU together with this packet's header, token,
+    /// options and a Block option. Returns None if not even a 16 byte block
+    /// fits, or if the options can't be encoded.
+    pub fn recommended_block_szx(&self, mtu: usize) -> Option<u8> {
+        let is_block = |option: &&OptionPair| {
+            option.num == CoapOption::Block1.into() || option.num == CoapOption::Block2.into()
+        };
+        // Existing Block options are counted at their largest size, two
+        // header and three value bytes, as is the one to be added.
+        let blocks = self.options().filter(is_block).count().max(1);
+        let other_options =
+            Self::encoded_options_len(self.options().filter(|o| !is_block(o))).ok()?;
+        let overhead = 4
+            + self.token_length_extension().len()
+            + self.token.len()
+            + other_options
+            + 5 * blocks
+            + 1;
+        let available = mtu.checked_sub(overhead)?;
+        (0..=6u8).rev().find(|szx| 16 << szx <= available)
+    }
+
+    /// Computes the CRC-16/CCITT-FALSE of the encoded packet without
+    /// serializing it, for links without their own integrity check. Returns
+    /// the error `to_bytes_extended_token` would for a packet it can't
+    /// encode, so the CRC always covers bytes that can be sent.
+    pub fn crc16(&self) -> Result<u16, MessageError> {
+        self.check_encodable(true)?;
+        if self.encoded_len()? > PACKET_MAX_SIZE {
+            return Err(MessageError::InvalidPacketLength);
+        }
+        let mut crc = 0xFFFF;
+        let mut update = |bytes: &[u8]| {
+            for &b in bytes {
+                crc ^= (b as u16) << 8;
+                for _ in 0..8 {
+                    crc = if crc & 0x8000 != 0 {
+                        (crc << 1) ^ 0x1021
+                    } else {
+                        crc << 1
+                    };
+                }
+            }
+        };
+        update(&[self.ver_type_tkl, self.code.into()]);
+        update(&self.message_id.to_be_bytes());
+        update(&self.token_length_extension());
+        update(self.token);
+        let mut previous = 0;
+        for option in self.options() {
+            let delta = option
+                .num
+                .checked_sub(previous)
+                .ok_or(MessageError::InvalidOptionDelta)?;
+            update(&Self::encode_option_header(delta, option.value.len())?);
+            update(option.value);
+            previous = option.num;
+        }
+        if self.has_payload() {
+            update(&[0xFF]);
+            update(self.payload);
+        }
+        Ok(crc)
+    }
+
+    /// Encodes the option delta and length, with their extended forms.
+    fn encode_option_header(delta: u16, length: usize) -> Result<Vec<u8, 5>, MessageError> {
+        let mut header = Vec::<u8, 5>::new();
+        let mut byte: u8 = 0;
+        if delta <= 12 {
+            byte |= (delta << 4) as u8;
+        } else if delta < 269 {
+            byte |= 13 << 4;
+        } else {
+            byte |= 14 << 4;
+        }
+        if length <= 12 {
+            byte |= length as u8;
+        } else if length < 269 {
+            byte |= 13;
+        } else {
+            byte |= 14;
+        }
+        let _ = header.push(byte);
+
+        if delta > 12 && delta < 269 {
+            let _ = header.push((delta - 13) as u8);
+        } else if delta >= 269 {
+            let fix = delta - 269;
+            let _ = header.push((fix >> 8) as u8);
+            let _ = header.push((fix & 0xFF) as u8);
+        }
+
+        if length > 12 && length < 269 {
+            let _ = header.push((length - 13) as u8);
+        } else if length >= 269 {
+            let fix = match u16::try_from(length - 269) {
+                Ok(fix) => fix,
+                Err(_) => return Err(MessageError::InvalidOptionLength),
+            };
+            let _ = header.push((fix >> 8) as u8);
+            let _ = header.push((fix & 0xFF) as u8);
+        }
+        Ok(header)
+    }
+
+    fn fmt_hex<W: Write>(w: &mut W, bytes: &[u8]) -> core::fmt::Result {
+        for b in bytes {
+            write!(w, "{:02x}", b)?;
+        }
+        Ok(())
+    }
+
+    /// Decodes a big-endian uint option value, where zero-length is the
+    /// canonical encoding of 0 (RFC 7252, section 3.2).
+    fn to_uint<T>(&self, encoded: &[u8]) -> Result<T, IncompatibleOptionValueFormat>
+    where
+        T: TryFrom<u64>,
+    {
+        let value_size = size_of::<T>();
+        if encoded.len() > value_size {
+            let mut s = String::<50>::new();
+            // The message always fits, so the write can't fail.
+            let _ = write!(
+                s,
+                "overflow: got {} bytes, expected {}",
+                encoded.len(),
+                value_size
+            );
+            return Err(IncompatibleOptionValueFormat { message: s });
+        }
+        let value = encoded
+            .iter()
+            .fold(0u64, |acc, &b| (acc << 8) | u64::from(b));
+        // At most size_of::<T>() bytes were folded, so the value fits.
+        T::try_from(value).map_err(|_| IncompatibleOptionValueFormat {
+            message: String::new(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn encode_uint_minimal() {
+        let mut buf = [0; 8];
+        assert_eq!(&[] as &[u8], encode_uint(0, &mut buf).unwrap());
+        assert_eq!(&[0x0B, 0xB8], encode_uint(3000, &mut buf).unwrap());
+        assert_eq!(&[0xFF; 8], encode_uint(u64::MAX, &mut buf).unwrap());
+        assert_eq!(
+            MessageError::BufferTooSmall,
+            encode_uint(0x10000, &mut [0; 2]).unwrap_err()
+        );
+    }
+
+    #[test]
+    fn test_header_codes() {
+        for code in 0..255 {
+            let p = Packet::new(
+                MessageType::Confirmable,
+                code.into(),
+                /* version= */ 1,
+                /* message_id= */ 1,
+                /* token= */ &[],
+                /* options= */ &mut Vec::new(),
+                /* payload= */ &[],
+            );
+            let class: MessageClass = code.into();
+            // valid items
+            if !matches!(class, MessageClass::Reserved(_)) {
+                assert_eq!(u8::from(class), code);
+                assert_eq!(class, p.get_code());
+            }
+        }
+    }
+
+    #[test]
+    fn request_response_class() {
+        let class: MessageClass = RequestType::Get.into();
+        assert_eq!(MessageClass::Request(RequestType::Get), class);
+        assert_eq!(0x01, u8::from(class));
+        let class: MessageClass = ResponseType::NotFound.into();
+        assert_eq!(MessageClass::Response(ResponseType::NotFound), class);
+        assert_eq!(0x84, u8::from(class));
+        assert_eq!(class, MessageClass::from(0x84));
+    }
+
+    #[test]
+    fn encode_unknown_code() {
+        for code in [
+            MessageClass::Request(RequestType::UnKnown),
+            MessageClass::Response(ResponseType::UnKnown),
+        ] {
+            let packet = Packet::new(
+                MessageType::Confirmable,
+                code,
+                /* version= */ 1,
+                /* message_id= */ 1,
+                /* token= */ &[],
+                /* options= */ &mut Vec::new(),
+                /* payload= */ &[],
+            );
+            assert_eq!(MessageError::InvalidHeader, packet.to_bytes().unwrap_err());
+        }
+    }
+
+    #[test]
+    fn from_bytes_fail() {
+        let b: &[u8] = &[1, 2, 3];
+        let p = Packet::from_bytes(b);
+        assert_eq!(MessageError::InvalidPacketLength, p.unwrap_err());
+    }
+
+    #[test]
+    fn needs_more_bytes() {
+        assert_eq!(
+            BufferStatus::Incomplete(1),
+            Packet::needs_more_bytes(&[0x46, 0x01, 0x00])
+        );
+        assert_eq!(
+            BufferStatus::Incomplete(5),
+            Packet::needs_more_bytes(&[0x46, 0x01, 0x00, 0x01, 0xAA])
+        );
+        assert_eq!(
+            BufferStatus::Complete,
+            Packet::needs_more_bytes(&[0x41, 0x01, 0x00, 0x01, 0xAA])
+        );
+        assert_eq!(
+            BufferStatus::Invalid,
+            Packet::needs_more_bytes(&[0x4D, 0x01, 0x00, 0x01, 0xAA])
+        );
+    }
+
+    #[test]
+    fn types() {
+        let p_acked = Packet::new(
+            MessageType::Acknowledgement,
+            MessageClass::Request(RequestType::Get),
+            /* version= */ 1,
+            /* message_id= */ 1,
+            /* token= */ &[],
+            /* options= */ &mut Vec::new(),
+            /* payload= */ &[],
+        );
+        assert_eq!(MessageType::Acknowledgement, p_acked.get_type());
+        let p_confirmed = Packet::new(
+            MessageType::Confirmable,
+            MessageClass::Request(RequestType::Get),
+            /* version= */ 1,
+            /* message_id= */ 1,
+            /* token= */ &[],
+            /* options= */ &mut Vec::new(),
+            /* payload= */ &[],
+        );
+        assert_eq!(MessageType::Confirmable, p_confirmed.get_type());
+        let p_notconfirmed = Packet::new(
+            MessageType::NonConfirmable,
+            MessageClass::Request(RequestType::Get),
+            /* version= */ 1,
+            /* message_id= */ 1,
+            /* token= */ &[],
+            /* options= */ &mut Vec::new(),
+            /* payload= */ &[],
+        );
+        assert_eq!(MessageType::NonConfirmable, p_notconfirmed.get_type());
+        let p_reset = Packet::new(
+            MessageType::Reset,
+            MessageClass::Request(RequestType::Get),
+            /* version= */ 1,
+            /* message_id= */ 1,
+            /* token= */ &[],
+            /* options= */ &mut Vec::new(),
+            /* payload= */ &[],
+        );
+        assert_eq!(MessageType::Reset, p_reset.get_type());
+    }
+
+    #[test]
+    fn test_decode_packet_with_options() {
+        let buf = [
+            0x44, 0x01, 0x84, 0x9e, 0x51, 0x55, 0x77, 0xe8, 0xb2, 0x48, 0x69, 0x04, 0x54, 0x65,
+            0x73, 0x74, 0x43, 0x61, 0x3d, 0x31,
+        ];
+        let packet = Packet::from_bytes(&buf);
+        assert!(packet.is_ok());
+        let packet = packet.unwrap();
+        assert_eq!(packet.get_version(), 1);
+        assert_eq!(packet.get_type(), MessageType::Confirmable);
+        assert_eq!(packet.get_token_length(), 4);
+        assert_eq!(packet.get_code(), MessageClass::Request(RequestType::Get));
+        assert_eq!(packet.get_message_id(), 33950);
+        assert_eq!(packet.get_token(), &[0x51, 0x55, 0x77, 0xE8]);
+        assert_eq!(packet.options.len(), 3);
+
+        let mut uri_path_iter = packet.get_options(CoapOption::UriPath);
+        let patch_part1 = uri_path_iter.next();
+        assert!(patch_part1.is_some());
+        assert_eq!(patch_part1.unwrap().value, "Hi".as_bytes());
+        let patch_part2 = uri_path_iter.next();
+        assert!(patch_part2.is_some());
+        assert_eq!(patch_part2.unwrap().value, "Test".as_bytes());
+        assert!(uri_path_iter.next().is_none());
+
+        let mut uri_query_iter = packet.get_options(CoapOption::UriQuery);
+        let uri_query_item = uri_query_iter.next();
+        assert!(uri_query_item.is_some());
+        assert_eq!(uri_query_item.unwrap().value, "a=1".as_bytes());
+        assert!(uri_query_iter.next().is_none());
+    }
+
+    #[test]
+    fn raw_header_bytes() {
+        let packet = Packet::new(
+            MessageType::Acknowledgement,
+            MessageClass::Response(ResponseType::Content),
+            /* version= */ 1,
+            /* message_id= */ 5117,
+            /* token= */ &[0xD0, 0xE2, 0x4D, 0xAC],
+            /* options= */ &mut Vec::new(),
+            /* payload= */ "Hello".as_bytes(),
+        );
+        let bytes = packet.to_bytes().unwrap();
+        assert_eq!(bytes[0], packet.raw_header_byte());
+        assert_eq!(bytes[1], packet.code_byte());
+    }
+
+    #[test]
+    fn typed_options() {
+        let buf = [
+            0x44, 0x01, 0x84, 0x9e, 0x51, 0x55, 0x77, 0xe8, 0xb2, 0x48, 0x69, 0x04, 0x54, 0x65,
+            0x73, 0x74, 0x43, 0x61, 0x3d, 0x31,
+        ];
+        let packet = Packet::from_bytes(&buf).unwrap();
+        let options: Vec<(CoapOption, &[u8]), 3> = packet.typed_options().collect();
+        assert_eq!(
+            options,
+            [
+                (CoapOption::UriPath, b"Hi".as_slice()),
+                (CoapOption::UriPath, b"Test".as_slice()),
+                (CoapOption::UriQuery, b"a=1".as_slice()),
+            ]
+        );
+    }
+
+    #[test]
+    fn option_byte_range() {
+        let buf = [
+            0x44, 0x01, 0x84, 0x9e, 0x51, 0x55, 0x77, 0xe8, 0xb2, 0x48, 0x69, 0x04, 0x54, 0x65,
+            0x73, 0x74, 0x43, 0x61, 0x3d, 0x31,
+        ];
+        let packet = Packet::from_bytes(&buf).unwrap();
+        assert_eq!(Some((8, 11)), packet.option_byte_range(0));
+        assert_eq!(Some((11, 16)), packet.option_byte_range(1));
+        assert_eq!(&[0x04, 0x54, 0x65, 0x73, 0x74], &buf[11..16]);
+        assert_eq!(Some((16, 20)), packet.option_byte_range(2));
+        assert_eq!(None, packet.option_byte_range(3));
+
+        let built = Packet::new(
+            MessageType::Confirmable,
+            MessageClass::Request(RequestType::Get),
+            /* version= */ 1,
+            /* message_id= */ 33950,
+            /* token= */ &[0x51, 0x55, 0x77, 0xE8],
+            /* options= */ &mut packet.options.clone(),
+            /* payload= */ &[],
+        );
+        assert_eq!(buf, &built.to_bytes().unwrap()[..]);
+        assert_eq!(None, built.option_byte_range(1));
+
+        let mut mutated = packet.clone();
+        mutated.options.remove(0);
+        assert_eq!(None, mutated.option_byte_range(0));
+        let mut mutated = packet.clone();
+        mutated.options[2].value = b"b=2";
+        assert_eq!(Some((11, 16)), mutated.option_byte_range(1));
+        assert_eq!(None, mutated.option_byte_range(2));
+
+        // GET with TKL 10 and a Uri-Path of "Hi".
+        let lenient = [
+            0x4A, 0x01, 0x00, 0x2A, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0A,
+            0xb2, 0x48, 0x69,
+        ];
+        let packet = Packet::from_bytes_lenient(&lenient).unwrap();
+        assert_eq!(Some((14, 17)), packet.option_byte_range(0));
+    }
+
+    #[test]
+    fn test_decode_packet_with_payload() {
+        let buf = [
+            0x64, 0x45, 0x13, 0xFD, 0xD0, 0xE2, 0x4D, 0xAC, 0xFF, 0x48, 0x65, 0x6C, 0x6C, 0x6F,
+        ];
+        let packet = Packet::from_bytes(&buf);
+        assert!(packet.is_ok());
+        let packet = packet.unwrap();
+        assert_eq!(packet.get_version(), 1);
+        assert_eq!(packet.get_type(), MessageType::Acknowledgement);
+        assert_eq!(packet.get_token_length(), 4);
+        assert_eq!(
+            packet.get_code(),
+            MessageClass::Response(ResponseType::Content)
+        );
+        assert_eq!(packet.message_id, 5117);
+        assert_eq!(packet.get_token(), &[0xD0, 0xE2, 0x4D, 0xAC]);
+        assert_eq!(packet.payload, "Hello".as_bytes().to_vec());
+    }
+
+    #[test]
+    fn payload_byte_range() {
+        let buf = [
+            0x64, 0x45, 0x13, 0xFD, 0xD0, 0xE2, 0x4D, 0xAC, 0xFF, 0x48, 0x65, 0x6C, 0x6C, 0x6F,
+        ];
+        let packet = Packet::from_bytes(&buf).unwrap();
+        assert_eq!(Some((9, 14)), packet.payload_byte_range());
+        assert_eq!(b"Hello", &buf[9..14]);
+
+        let packet = Packet::from_bytes(&buf[..8]).unwrap();
+        assert_eq!(None, packet.payload_byte_range());
+        let built = packet_with_payload(&[], b"Hello");
+        assert_eq!(None, built.payload_byte_range());
+
+        let mut replaced = Packet::from_bytes(&buf).unwrap();
+        replaced.payload = &buf[10..];
+        assert_eq!(Some((10, 14)), replaced.payload_byte_range());
+        replaced.payload = b"Hello";
+        assert_eq!(None, replaced.payload_byte_range());
+    }
+
+    #[test]
+    fn test_encode_packet_with_options() {
+        let options = &[
+            OptionPair {
+                num: CoapOption::UriPath.into(),
+                value: "Hi".as_bytes(),
+            },
+            OptionPair {
+                num: CoapOption::UriPath.into(),
+                value: "Test".as_bytes(),
+            },
+            OptionPair {
+                num: CoapOption::UriQuery.into(),
+                value: "a=1".as_bytes(),
+            },
+        ];
+        let packet = Packet::new(
+            MessageType::Confirmable,
+            MessageClass::Request(RequestType::Get),
+            /* version= */ 1,
+            /* message_id= */ 33950,
+            /* token= */ &[0x51, 0x55, 0x77, 0xE8],
+            /* options= */ &mut Vec::from_slice(options).unwrap(),
+            /* payload= */ &[],
+        );
+        assert_eq!(
+            packet.to_bytes().unwrap(),
+            &[
+                0x44, 0x01, 0x84, 0x9e, 0x51, 0x55, 0x77, 0xe8, 0xb2, 0x48, 0x69, 0x04, 0x54, 0x65,
+                0x73, 0x74, 0x43, 0x61, 0x3d, 0x31
+            ]
+        );
+    }
+
+    #[test]
+    fn test_encode_packet_with_payload() {
+        let packet = Packet::new(
+            MessageType::Acknowledgement,
+            MessageClass::Response(ResponseType::Content),
+            /* version= */ 1,
+            /* message_id= */ 5117,
+            /* token= */ &[0xD0, 0xE2, 0x4D, 0xAC],
+            /* options= */ &mut Vec::new(),
+            /* payload= */ "Hello".as_bytes(),
+        );
+        assert_eq!(
+            packet.to_bytes().unwrap(),
+            &[0x64, 0x45, 0x13, 0xFD, 0xD0, 0xE2, 0x4D, 0xAC, 0xFF, 0x48, 0x65, 0x6C, 0x6C, 0x6F]
+        );
+    }
+
+    #[test]
+    fn test_encode_packet_without_payload() {
+        let packet = Packet::new(
+            MessageType::Acknowledgement,
+            MessageClass::Response(ResponseType::Content),
+            /* version= */ 1,
+            /* message_id= */ 5117,
+            /* token= */ &[0xD0, 0xE2, 0x4D, 0xAC],
+            /* options= */ &mut Vec::new(),
+            /* payload= */ &[],
+        );
+        assert!(!packet.has_payload());
+        assert_eq!(
+            packet.to_bytes().unwrap(),
+            &[0x64, 0x45, 0x13, 0xFD, 0xD0, 0xE2, 0x4D, 0xAC]
+        );
+    }
+
+    #[test]
+    fn options_encoded_len() {
+        let value = [0xAB; 300];
+        let options = &[
+            OptionPair {
+                num: CoapOption::UriPath.into(),
+                value: b"Hi",
+            },
+            OptionPair {
+                num: CoapOption::UriPath.into(),
+                value: b"Test",
+            },
+            OptionPair {
+                num: CoapOption::ProxyUri.into(),
+                value: &value[..20],
+            },
+            OptionPair {
+                num: CoapOption::NoResponse.into(),
+                value: &value,
+            },
+        ];
+        let packet = Packet::new(
+            MessageType::Confirmable,
+            MessageClass::Request(RequestType::Get),
+            /* version= */ 1,
+            /* message_id= */ 33950,
+            /* token= */ &[0x51, 0x55, 0x77, 0xE8],
+            /* options= */ &mut Vec::from_slice(options).unwrap(),
+            /* payload= */ b"Hello",
+        );
+        let payload_with_marker_len = 1 + packet.payload.len();
+        assert_eq!(
+            packet.options_encoded_len().unwrap(),
+            packet.to_bytes().unwrap().len() - 4 - packet.token.len() - payload_with_marker_len
+        );
+
+        let mut unsorted = packet.clone();
+        unsorted.options.swap(0, 3);
+        assert_eq!(
+            Err(MessageError::InvalidOptionDelta),
+            unsorted.options_encoded_len()
+        );
+        assert_eq!(
+            Err(MessageError::InvalidOptionDelta),
+            unsorted.encoded_len()
+        );
+        assert!(!unsorted.fits_mtu(1280));
+        assert_eq!(None, unsorted.recommended_block_szx(1280));
+    }
+
+    #[test]
+    fn test_encode_decode_content_format() {
+        let options = &[OptionPair {
+            num: CoapOption::ContentFormat.into(),
+            value: &u16::try_from(usize::from(ContentFormat::TextPlain))
+                .unwrap()
+                .to_be_bytes(),
+        }];
+        let packet = Packet::new(
+            MessageType::NonConfirmable,
+            MessageClass::Request(RequestType::Get),
+            /* version= */ 1,
+            /* message_id= */ 5117,
+            /* token= */ &[0xD0, 0xE2, 0x4D, 0xAC],
+            /* options= */ &mut Vec::from_slice(options).unwrap(),
+            /* payload= */ "Hello".as_bytes(),
+        );
+        assert_eq!(
+            ContentFormat::TextPlain,
+            ContentFormat::try_from(packet.get_content_format_value().unwrap() as usize)
+                .ok()
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_encode_decode_content_format_without_msb() {
+        let options = &[OptionPair {
+            num: CoapOption::ContentFormat.into(),
+            value: &u16::try_from(usize::from(ContentFormat::ApplicationJSON))
+                .unwrap()
+                .to_be_bytes(),
+        }];
+        let packet = Packet::new(
+            MessageType::NonConfirmable,
+            MessageClass::Request(RequestType::Get),
+            /* version= */ 1,
+            /* message_id= */ 5117,
+            /* token= */ &[0xD0, 0xE2, 0x4D, 0xAC],
+            /* options= */ &mut Vec::from_slice(options).unwrap(),
+            /* payload= */ "Hello".as_bytes(),
+        );
+        assert_eq!(
+            ContentFormat::ApplicationJSON,
+            ContentFormat::try_from(packet.get_content_format_value().unwrap() as usize)
+                .ok()
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_encode_decode_content_format_with_msb() {
+        let options = &[OptionPair {
+            num: CoapOption::ContentFormat.into(),
+            value: &u16::try_from(usize::from(ContentFormat::ApplicationSensmlXML))
+                .unwrap()
+                .to_be_bytes(),
+        }];
+        let packet = Packet::new(
+            MessageType::NonConfirmable,
+            MessageClass::Request(RequestType::Get),
+            /* version= */ 1,
+            /* message_id= */ 5117,
+            /* token= */ &[0xD0, 0xE2, 0x4D, 0xAC],
+            /* options= */ &mut Vec::from_slice(options).unwrap(),
+            /* payload= */ "Hello".as_bytes(),
+        );
+        assert_eq!(
+            ContentFormat::ApplicationSensmlXML,
+            ContentFormat::try_from(packet.get_content_format_value().unwrap() as usize)
+                .ok()
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_decode_empty_content_format() {
+        let packet = Packet::new(
+            MessageType::NonConfirmable,
+            MessageClass::Request(RequestType::Get),
+            /* version= */ 1,
+            /* message_id= */ 5117,
+            /* token= */ &[0xD0, 0xE2, 0x4D, 0xAC],
+            /* options= */ &mut Vec::new(),
+            /* payload= */ "Hello".as_bytes(),
+        );
+        assert!(packet.get_content_format_value().is_none());
+    }
+
+    #[test]
+    fn test_decode_zero_length_content_format() {
+        let options = &[OptionPair {
+            num: CoapOption::ContentFormat.into(),
+            value: &[],
+        }];
+        let packet = Packet::new(
+            MessageType::NonConfirmable,
+            MessageClass::Request(RequestType::Get),
+            /* version= */ 1,
+            /* message_id= */ 5117,
+            /* token= */ &[0xD0, 0xE2, 0x4D, 0xAC],
+            /* options= */ &mut Vec::from_slice(options).unwrap(),
+            /* payload= */ "Hello".as_bytes(),
+        );
+        assert_eq!(Some(0), packet.get_content_format_value());
+        assert_eq!(Some(ContentFormat::TextPlain), packet.get_content_format());
+
+        let bytes = packet.to_bytes().unwrap();
+        let decoded = Packet::from_bytes(&bytes).unwrap();
+        assert_eq!(Some(ContentFormat::TextPlain), decoded.get_content_format());
+    }
+
+    #[test]
+    fn content_format_family() {
+        let senml_cbor = u16::try_from(usize::from(ContentFormat::ApplicationSenmlCBOR))
+            .unwrap()
+            .to_be_bytes();
+        let options = &[OptionPair {
+            num: CoapOption::ContentFormat.into(),
+            value: &senml_cbor,
+        }];
+        let packet = Packet::new(
+            MessageType::NonConfirmable,
+            MessageClass::Response(ResponseType::Content),
+            /* version= */ 1,
+            /* message_id= */ 5117,
+            /* token= */ &[],
+            /* options= */ &mut Vec::from_slice(options).unwrap(),
+            /* payload= */ &[],
+        );
+        assert!(packet.is_senml());
+        assert!(packet.is_cbor());
+        assert!(!packet.is_json());
+
+        let options = &[OptionPair {
+            num: CoapOption::ContentFormat.into(),
+            value: &[0],
+        }];
+        let packet = Packet::new(
+            MessageType::NonConfirmable,
+            MessageClass::Response(ResponseType::Content),
+            /* version= */ 1,
+            /* message_id= */ 5117,
+            /* token= */ &[],
+            /* options= */ &mut Vec::from_slice(options).unwrap(),
+            /* payload= */ &[],
+        );
+        assert_eq!(Some(ContentFormat::TextPlain), packet.get_content_format());
+        assert!(!packet.is_senml());
+        assert!(!packet.is_cbor());
+        assert!(!packet.is_json());
+    }
+
+    fn packet_with_payload<'a>(options: &[OptionPair<'a>], payload: &'a [u8]) -> Packet<'a> {
+        Packet::new(
+            MessageType::NonConfirmable,
+            MessageClass::Response(ResponseType::Content),
+            /* version= */ 1,
+            /* message_id= */ 5117,
+            /* token= */ &[],
+            /* options= */ &mut Vec::from_slice(options).unwrap(),
+            /* payload= */ payload,
+        )
+    }
+
+    #[test]
+    fn guess_content_format() {
+        let json = packet_with_payload(&[], b" {\"temp\": 21}");
+        assert_eq!(
+            Some(ContentFormat::ApplicationJSON),
+            json.guess_content_format()
+        );
+        let png = packet_with_payload(&[], &[0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A, 0x00]);
+        assert_eq!(Some(ContentFormat::ImagePng), png.guess_content_format());
+        let cbor = packet_with_payload(&[], &[0xA1, 0x01, 0x02]);
+        assert_eq!(
+            Some(ContentFormat::ApplicationCBOR),
+            cbor.guess_content_format()
+        );
+        let cbor = packet_with_payload(&[], &[0x98, 0x02, 0x01, 0x02]);
+        assert_eq!(
+            Some(ContentFormat::ApplicationCBOR),
+            cbor.guess_content_format()
+        );
+        // Map of one pair with a single byte, array of 24 items in 2 bytes,
+        // and a length argument cut short.
+        for binary in [&[0xA1, 0x01][..], &[0x98, 0x18, 0x01], &[0x99, 0x01]] {
+            let packet = packet_with_payload(&[], binary);
+            assert_eq!(None, packet.guess_content_format());
+        }
+        let text = packet_with_payload(&[], b"Hello");
+        assert_eq!(None, text.guess_content_format());
+        let declared = packet_with_payload(
+            &[OptionPair {
+                num: CoapOption::ContentFormat.into(),
+                value: &[],
+            }],
+            b"{}",
+        );
+        assert_eq!(None, declared.guess_content_format());
+    }
+
+    #[test]
+    fn suppress_response() {
+        let packet = Packet::new(
+            MessageType::NonConfirmable,
+            MessageClass::Request(RequestType::Get),
+            /* version= */ 1,
+            /* message_id= */ 5117,
+            /* token= */ &[],
+            /* options= */ &mut Vec::new(),
+            /* payload= */ &[],
+        );
+        assert!(!packet.should_suppress_response(ResponseType::Content));
+        assert!(!packet.should_suppress_response(ResponseType::NotFound));
+
+        let options = &[OptionPair {
+            num: CoapOption::NoResponse.into(),
+            value: &[2],
+        }];
+        let packet = Packet::new(
+            MessageType::NonConfirmable,
+            MessageClass::Request(RequestType::Get),
+            /* version= */ 1,
+            /* message_id= */ 5117,
+            /* token= */ &[],
+            /* options= */ &mut Vec::from_slice(options).unwrap(),
+            /* payload= */ &[],
+        );
+        assert_eq!(Some(2), packet.get_no_response_value());
+        assert!(packet.should_suppress_response(ResponseType::Content));
+        assert!(!packet.should_suppress_response(ResponseType::NotFound));
+        assert!(!packet.should_suppress_response(ResponseType::InternalServerError));
+    }
+
+    #[test]
+    fn option_uint_widths() {
+        let options = &[
+            OptionPair {
+                num: CoapOption::Observe.into(),
+                value: &[0x01, 0x02, 0x03],
+            },
+            OptionPair {
+                num: CoapOption::MaxAge.into(),
+                value: &[0x01, 0x02, 0x03, 0x04, 0x05],
+            },
+            OptionPair {
+                num: CoapOption::Accept.into(),
+                value: &[0x32],
+            },
+        ];
+        let packet = Packet::new(
+            MessageType::NonConfirmable,
+            MessageClass::Request(RequestType::Get),
+            /* version= */ 1,
+            /* message_id= */ 5117,
+            /* token= */ &[],
+            /* options= */ &mut Vec::from_slice(options).unwrap(),
+            /* payload= */ &[],
+        );
+        assert_eq!(Some(Ok(0x32)), packet.option_u8(CoapOption::Accept));
+        assert_eq!(Some(Ok(0x32)), packet.option_u16(CoapOption::Accept));
+        assert!(packet.option_u16(CoapOption::Observe).unwrap().is_err());
+        assert_eq!(Some(Ok(0x010203)), packet.option_u32(CoapOption::Observe));
+        assert!(packet.option_u32(CoapOption::MaxAge).unwrap().is_err());
+        assert_eq!(
+            Some(Ok(0x0102030405)),
+            packet.option_u64(CoapOption::MaxAge)
+        );
+        assert_eq!(None, packet.option_u64(CoapOption::Size1));
+    }
+
+    #[test]
+    fn cache_key() {
+        let request = |etag: &'static [u8], path: &'static [u8]| {
+            let options = &[
+                OptionPair {
+                    num: CoapOption::ETag.into(),
+                    value: etag,
+                },
+                OptionPair {
+                    num: CoapOption::UriPath.into(),
+                    value: path,
+                },
+                OptionPair {
+                    num: CoapOption::Size1.into(),
+                    value: &[0x10],
+                },
+            ];
+            Packet::new(
+                MessageType::Confirmable,
+                MessageClass::Request(RequestType::Get),
+                /* version= */ 1,
+                /* message_id= */ 1,
+                /* token= */ &[],
+                /* options= */ &mut Vec::from_slice(options).unwrap(),
+                /* payload= */ &[],
+            )
+        };
+        assert!(CoapOption::Size1.is_no_cache_key());
+        assert!(!CoapOption::UriPath.is_no_cache_key());
+        assert_eq!(
+            request(&[1], b"temp").cache_key_hash(),
+            request(&[2], b"temp").cache_key_hash()
+        );
+        assert_ne!(
+            request(&[1], b"temp").cache_key_hash(),
+            request(&[1], b"humidity").cache_key_hash()
+        );
+    }
+
+    #[test]
+    fn normalize_for_cache() {
+        let request = |max_age: &'static [u8]| {
+            let options = &[
+                OptionPair {
+                    num: CoapOption::UriPath.into(),
+                    value: b"temp",
+                },
+                OptionPair {
+                    num: CoapOption::MaxAge.into(),
+                    value: max_age,
+                },
+                OptionPair {
+                    num: CoapOption::Size1.into(),
+                    value: &[0x10],
+                },
+            ];
+            Packet::new(
+                MessageType::Confirmable,
+                MessageClass::Request(RequestType::Get),
+                /* version= */ 1,
+                /* message_id= */ 1,
+                /* token= */ &[],
+                /* options= */ &mut Vec::from_slice(options).unwrap(),
+                /* payload= */ &[],
+            )
+        };
+        let mut first = request(&[60]);
+        let mut second = request(&[0x0E, 0x10]);
+        assert_ne!(first, second);
+
+        first.normalize_for_cache();
+        second.normalize_for_cache();
+        assert_eq!(first, second);
+        assert_eq!(1, first.options.len());
+        assert_eq!(first.cache_key_hash(), second.cache_key_hash());
+    }
+
+    #[test]
+    fn option_value_array() {
+        let option = OptionPair {
+            num: CoapOption::ETag.into(),
+            value: &[1, 2, 3, 4],
+        };
+        assert_eq!(Some([1, 2, 3, 4]), option.value_array::<4>());
+        assert_eq!(None, option.value_array::<3>());
+        let option = OptionPair {
+            num: CoapOption::ETag.into(),
+            value: &[1, 2, 3],
+        };
+        assert_eq!(None, option.value_array::<4>());
+    }
+
+    #[test]
+    fn proxy_options() {
+        let request = |options: &[OptionPair<'static>]| {
+            Packet::new(
+                MessageType::Confirmable,
+                MessageClass::Request(RequestType::Get),
+                /* version= */ 1,
+                /* message_id= */ 1,
+                /* token= */ &[],
+                /* options= */ &mut Vec::from_slice(options).unwrap(),
+                /* payload= */ &[],
+            )
+        };
+        let proxy_uri = OptionPair {
+            num: CoapOption::ProxyUri.into(),
+            value: b"coap://example.com/temp",
+        };
+        let proxy_scheme = OptionPair {
+            num: CoapOption::ProxyScheme.into(),
+            value: b"coap",
+        };
+        let uri_host = OptionPair {
+            num: CoapOption::UriHost.into(),
+            value: b"example.com",
+        };
+        let uri_path = OptionPair {
+            num: CoapOption::UriPath.into(),
+            value: b"temp",
+        };
+
+        assert_eq!(Ok(()), request(&[proxy_uri]).validate_proxy_options());
+        assert_eq!(
+            Err(MessageError::ProxyUriWithUriOptions),
+            request(&[proxy_uri, uri_path]).validate_proxy_options()
+        );
+        assert_eq!(
+            Ok(()),
+            request(&[proxy_scheme, uri_host]).validate_proxy_options()
+        );
+        assert_eq!(
+            Err(MessageError::ProxySchemeWithoutUriHost),
+            request(&[proxy_scheme, uri_path]).validate_proxy_options()
+        );
+    }
+
+    #[test]
+    fn option() {
+        for i in 0..512 {
+            assert_eq!(i, CoapOption::from(i).into());
+        }
+    }
+
+    #[test]
+    fn option_table() {
+        for &(number, option) in OPTION_TABLE {
+            assert_eq!(option, CoapOption::from(number));
+            assert_eq!(number, u16::from(option));
+        }
+        assert_eq!(CoapOption::NoResponse, CoapOption::from(258));
+        assert_eq!(CoapOption::Unknown(259), CoapOption::from(259));
+    }
+
+    #[test]
+    fn content_format_table() {
+        for &(number, format) in CONTENT_FORMAT_TABLE {
+            assert_eq!(Ok(format), ContentFormat::try_from(number));
+            assert_eq!(number, usize::from(format));
+        }
+        assert_eq!(Err(InvalidContentFormat), ContentFormat::try_from(1));
+    }
+
+    #[test]
+    fn message_class_table() {
+        for &(number, class) in MESSAGE_CLASS_TABLE {
+            assert_eq!(class, MessageClass::from(number));
+            assert_eq!(number, u8::from(class));
+        }
+        assert_eq!(MessageClass::Reserved(0x08), MessageClass::from(0x08));
+        assert_eq!(0x08, u8::from(MessageClass::Reserved(0x08)));
+    }
+
+    #[test]
+    fn option_value_format() {
+        assert_eq!(
+            OptionValueFormat::String,
+            CoapOption::UriPath.value_format()
+        );
+        assert_eq!(
+            OptionValueFormat::Uint,
+            CoapOption::ContentFormat.value_format()
+        );
+        assert_eq!(
+            OptionValueFormat::Empty,
+            CoapOption::IfNoneMatch.value_format()
+        );
+        assert_eq!(OptionValueFormat::Opaque, CoapOption::ETag.value_format());
+        assert_eq!(
+            OptionValueFormat::Opaque,
+            CoapOption::RequestTag.value_format()
+        );
+        assert_eq!(
+            OptionValueFormat::Opaque,
+            CoapOption::Unknown(65001).value_format()
+        );
+    }
+
+    #[test]
+    fn unrecognized_critical() {
+        let known = [CoapOption::UriPath, CoapOption::ContentFormat];
+        let options = &[
+            OptionPair {
+                num: CoapOption::UriPath.into(),
+                value: b"Hi",
+            },
+            OptionPair {
+                num: 65000,
+                value: &[1],
+            },
+        ];
+        let mut packet = Packet::new(
+            MessageType::Confirmable,
+            MessageClass::Request(RequestType::Get),
+            /* version= */ 1,
+            /* message_id= */ 5117,
+            /* token= */ &[],
+            /* options= */ &mut Vec::from_slice(options).unwrap(),
+            /* payload= */ &[],
+        );
+        assert!(CoapOption::UriPath.is_critical());
+        assert!(!CoapOption::Unknown(65000).is_critical());
+        assert_eq!(None, packet.first_unrecognized_critical(&known));
+
+        packet
+            .options
+            .push(OptionPair {
+                num: 65001,
+                value: &[1],
+            })
+            .unwrap();
+        assert_eq!(Some(65001), packet.first_unrecognized_critical(&known));
+    }
+
+    #[test]
+    fn custom_option() {
+        let options = &[
+            OptionPair {
+                num: CoapOption::UriPath.into(),
+                value: b"Hi",
+            },
+            OptionPair {
+                num: 65001,
+                value: b"old",
+            },
+            OptionPair {
+                num: 65003,
+                value: b"other",
+            },
+        ];
+        let mut packet = Packet::new(
+            MessageType::Confirmable,
+            MessageClass::Request(RequestType::Get),
+            /* version= */ 1,
+            /* message_id= */ 5117,
+            /* token= */ &[],
+            /* options= */ &mut Vec::from_slice(options).unwrap(),
+            /* payload= */ &[],
+        );
+        assert_eq!(Some(b"old".as_slice()), packet.get_custom_option(65001));
+        packet.set_custom_option(65001, b"vendor").unwrap();
+        packet.set_custom_option(65000, b"first").unwrap();
+        assert_eq!(Some(b"vendor".as_slice()), packet.get_custom_option(65001));
+        assert_eq!(None, packet.get_custom_option(65002));
+
+        let bytes = packet.to_bytes().unwrap();
+        let decoded = Packet::from_bytes(&bytes).unwrap();
+        let nums: Vec<u16, 4> = decoded.options().map(|p| p.num).collect();
+        assert_eq!(nums, [11, 65000, 65001, 65003]);
+        assert_eq!(Some(b"vendor".as_slice()), decoded.get_custom_option(65001));
+    }
+
+    #[test]
+    fn copy_option_value() {
+        let options = &[OptionPair {
+            num: CoapOption::UriHost.into(),
+            value: b"example.com",
+        }];
+        let packet = Packet::new(
+            MessageType::Confirmable,
+            MessageClass::Request(RequestType::Get),
+            /* version= */ 1,
+            /* message_id= */ 5117,
+            /* token= */ &[],
+            /* options= */ &mut Vec::from_slice(options).unwrap(),
+            /* payload= */ &[],
+        );
+        let mut out = [0; 16];
+        let len = packet
+            .copy_option_value(CoapOption::UriHost, &mut out)
+            .unwrap()
+            .unwrap();
+        assert_eq!(b"example.com", &out[..len]);
+        assert_eq!(
+            MessageError::BufferTooSmall,
+            packet
+                .copy_option_value(CoapOption::UriHost, &mut [0; 4])
+                .unwrap()
+                .unwrap_err()
+        );
+        assert!(packet
+            .copy_option_value(CoapOption::UriPath, &mut out)
+            .is_none());
+    }
+
+    #[test]
+    fn token_presence() {
+        let packet = Packet::from_bytes(&[0x40, 0x01, 0x00, 0x01]).unwrap();
+        assert!(!packet.has_token());
+        assert!(packet.token_matches(&[]));
+        assert!(!packet.token_matches(&[0x01]));
+
+        let packet = Packet::from_bytes(&[0x41, 0x01, 0x00, 0x01, 0x01]).unwrap();
+        assert!(packet.has_token());
+        assert!(packet.token_matches(&[0x01]));
+        assert!(!packet.token_matches(&[]));
+    }
+
+    #[test]
+    fn hash_map_keys() {
+        let mut formats = heapless::FnvIndexMap::<ContentFormat, &str, 4>::new();
+        formats
+            .insert(ContentFormat::ApplicationJSON, "json")
+            .unwrap();
+        formats
+            .insert(ContentFormat::ApplicationCBOR, "cbor")
+            .unwrap();
+        assert_eq!(Some(&"cbor"), formats.get(&ContentFormat::ApplicationCBOR));
+        assert_eq!(None, formats.get(&ContentFormat::TextPlain));
+
+        let mut handlers = heapless::FnvIndexMap::<CoapOption, u8, 4>::new();
+        handlers.insert(CoapOption::Unknown(65001), 1).unwrap();
+        handlers.insert(CoapOption::Unknown(65003), 2).unwrap();
+        assert_eq!(Some(&2), handlers.get(&CoapOption::Unknown(65003)));
+
+        let mut classes = heapless::FnvIndexMap::<MessageClass, u8, 4>::new();
+        classes.insert(RequestType::Get.into(), 1).unwrap();
+        classes.insert(MessageClass::Reserved(0xE1), 2).unwrap();
+        assert_eq!(Some(&2), classes.get(&MessageClass::Reserved(0xE1)));
+    }
+
+    #[test]
+    fn content_format() {
+        for i in 0..512 {
+            if let Ok(o) = ContentFormat::try_from(i) {
+                assert_eq!(i, o.into());
+            }
+        }
+    }
+
+    #[test]
+    fn observe_option() {
+        for i in 0..8 {
+            if let Ok(o) = ObserveOption::try_from(i) {
+                assert_eq!(i, o.into());
+            }
+        }
+    }
+
+    #[test]
+    fn options() {
+        let options = &[
+            OptionPair {
+                num: CoapOption::UriHost.into(),
+                value: &[0],
+            },
+            OptionPair {
+                num: CoapOption::UriPath.into(),
+                value: &[1],
+            },
+            OptionPair {
+                num: CoapOption::ETag.into(),
+                value: &[2],
+            },
+        ];
+        let packet = Packet::new(
+            MessageType::NonConfirmable,
+            MessageClass::Request(RequestType::Get),
+            /* version= */ 1,
+            /* message_id= */ 5117,
+            /* token= */ &[0xD0, 0xE2, 0x4D, 0xAC],
+            /* options= */ &mut Vec::from_slice(options).unwrap(),
+            /* payload= */ "Hello".as_bytes(),
+        );
+        assert_eq!(3, packet.options().len());
+
+        let bytes = packet.to_bytes().unwrap();
+        let pp = Packet::from_bytes(&bytes).unwrap();
+        assert_eq!(3, pp.options().len());
+    }
+
+    // #[test]
+    // fn test_option_u32_format() {
+    //     let options = &[
+    //         OptionPair {
+    //             num: CoapOption::Observe.into(),
+    //             value: [],
+    //         },
+    //         OptionPair {
+    //             num: CoapOption::Observe.into(),
+    //             value: &[1],
+    //         },
+    //         OptionPair {
+    //             num: CoapOption::Observe.into(),
+    //             value: &[2],
+    //         },
+    //     ];
+    //     let packet = Packet::new(
+    //         MessageType::NonConfirmable,
+    //         MessageClass::Request(RequestType::Get),
+    //         /* version= */ 1,
+    //         /* message_id= */ 5117,
+    //         /* token= */ &[0xD0, 0xE2, 0x4D, 0xAC],
+    //         /* options= */ &mut Vec::from_slice(options).unwrap(),
+    //         /* payload= */ "Hello".as_bytes(),
+    //     );
+    //     let values = vec![0, 100, 1000, 10000, u32::MAX];
+    //     let expected = values.iter().map(|&x| Ok(OptionValueU32(x))).collect();
+    //     assert_eq!(actual, Some(expected));
+    // }
+
+    // #[test]
+    // fn test_option_utf8_format() {
+    //     let mut p = Packet::new();
+    //     let option_key = CoapOption::UriPath;
+    //     let values = vec!["", "simple", "unicode 😁 stuff"];
+    //     for &value in &values {
+    //         p.add_option_as(option_key, OptionValueString(value.to_owned()));
+    //     }
+    //     let expected = values
+    //         .iter()
+    //         .map(|&x| Ok(OptionValueString(x.to_owned())))
+    //         .collect();
+    //     let actual = p.get_options_as::<OptionValueString>(option_key);
+    //     assert_eq!(actual, Some(expected));
+    // }
+
+    #[test]
+    fn observe_none() {
+        let packet = Packet::new(
+            MessageType::NonConfirmable,
+            MessageClass::Request(RequestType::Get),
+            /* version= */ 1,
+            /* message_id= */ 5117,
+            /* token= */ &[0xD0, 0xE2, 0x4D, 0xAC],
+            /* options= */ &mut Vec::new(),
+            /* payload= */ "Hello".as_bytes(),
+        );
+        assert_eq!(None, packet.get_observe_value());
+        // p.set_observe_value(0);
+        // assert_eq!(Some(Ok(0)), p.get_observe_value());
+    }
+
+    #[test]
+    fn observe_some() {
+        let options = &[OptionPair {
+            num: CoapOption::Observe.into(),
+            value: &[10],
+        }];
+        let packet = Packet::new(
+            MessageType::NonConfirmable,
+            MessageClass::Request(RequestType::Get),
+            /* version= */ 1,
+            /* message_id= */ 5117,
+            /* token= */ &[0xD0, 0xE2, 0x4D, 0xAC],
+            /* options= */ &mut Vec::from_slice(options).unwrap(),
+            /* payload= */ "Hello".as_bytes(),
+        );
+        assert_eq!(Some(10), packet.get_observe_value());
+    }
+
+    #[test]
+    fn options_limit_exceeded() {
+        let buf = [
+            0x40, 0x01, 0x00, 0x00, 0x61, 0x31, 0x01, 0x32, 0x01, 0x33, 0x01, 0x34, 0x01, 0x35,
+            0x01, 0x36, 0x01, 0x37, 0x01, 0x38, 0x01, 0x39, 0x02, 0x31, 0x30, 0x02, 0x31, 0x31,
+            0x02, 0x31, 0x32, 0x02, 0x31, 0x33, 0x02, 0x31, 0x34, 0x02, 0x31, 0x35, 0x02, 0x31,
+            0x36, 0x02, 0x31, 0x37, 0x02, 0x31, 0x38, 0x02, 0x31, 0x39, 0x02, 0x32, 0x30, 0x02,
+            0x32, 0x31, 0x02, 0x32, 0x32, 0x02, 0x32, 0x33, 0x02, 0x32, 0x34, 0x02, 0x32, 0x35,
+            0x02, 0x32, 0x36, 0x02, 0x32, 0x37, 0x02, 0x32, 0x38, 0x02, 0x32, 0x39, 0x02, 0x33,
+            0x30, 0x02, 0x33, 0x31, 0x02, 0x33, 0x32, 0x02, 0x33, 0x33,
+        ];
+        let p = Packet::from_bytes(&buf);
+        assert_eq!(MessageError::OptionsLimitExceeded, p.unwrap_err());
+    }
+
+    fn round_trip_option_value(len: usize) {
+        let value = [0xAB; 1000];
+        let options = &[OptionPair {
+            num: CoapOption::ProxyUri.into(),
+            value: &value[..len],
+        }];
+        let packet = Packet::new(
+            MessageType::Confirmable,
+            MessageClass::Request(RequestType::Get),
+            /* version= */ 1,
+            /* message_id= */ 1,
+            /* token= */ &[],
+            /* options= */ &mut Vec::from_slice(options).unwrap(),
+            /* payload= */ "Hello".as_bytes(),
+        );
+        let bytes = packet.to_bytes().unwrap();
+        let decoded = Packet::from_bytes(&bytes).unwrap();
+        assert_eq!(bytes, decoded.to_bytes().unwrap());
+        assert_eq!(
+            len,
+            decoded
+                .get_first_option(CoapOption::ProxyUri)
+                .unwrap()
+                .value
+                .len()
+        );
+    }
+
+    #[test]
+    fn extended_option_length() {
+        round_trip_option_value(268);
+        round_trip_option_value(269);
+        round_trip_option_value(270);
+        round_trip_option_value(1000);
+    }
+
+    #[test]
+    fn extended_option_length_overflow() {
+        let buf = [0x40, 0x01, 0x00, 0x00, 0xBE, 0xFF, 0xFF, 0x00];
+        let p = Packet::from_bytes(&buf);
+        assert_eq!(MessageError::InvalidOptionLength, p.unwrap_err());
+    }
+
+    #[test]
+    fn option_value_exceeds_packet_size() {
+        let value = [0xAB; PACKET_MAX_SIZE];
+        let options = &[OptionPair {
+            num: CoapOption::ProxyUri.into(),
+            value: &value,
+        }];
+        let packet = Packet::new(
+            MessageType::Confirmable,
+            MessageClass::Request(RequestType::Get),
+            /* version= */ 1,
+            /* message_id= */ 1,
+            /* token= */ &[],
+            /* options= */ &mut Vec::from_slice(options).unwrap(),
+            /* payload= */ &[],
+        );
+        assert_eq!(
+            MessageError::InvalidPacketLength,
+            packet.to_bytes().unwrap_err()
+        );
+    }
+
+    #[test]
+    fn pretty_print() {
+        let buf = [
+            0x44, 0x01, 0x84, 0x9e, 0x51, 0x55, 0x77, 0xe8, 0xb2, 0x48, 0x69, 0x04, 0x54, 0x65,
+            0x73, 0x74, 0x43, 0x61, 0x3d, 0x31,
+        ];
+        let packet = Packet::from_bytes(&buf).unwrap();
+        let mut s = String::<512>::new();
+        packet.fmt_pretty(&mut s).unwrap();
+        assert!(s.contains("Code: 0.01 Request(Get)"));
+        assert!(s.contains("Token: 515577e8"));
+        assert!(s.contains("Option UriPath: 4869"));
+        assert!(s.contains("Option UriPath: 54657374"));
+        assert!(s.contains("Option UriQuery: 613d31"));
+        assert!(s.contains("Payload: 0 bytes"));
+    }
+
+    #[test]
+    fn empty_const() {
+        const RST_TEMPLATE: Packet = Packet::empty_reset(0);
+        assert_eq!(MessageType::Reset, RST_TEMPLATE.get_type());
+        assert_eq!(MessageClass::Empty, RST_TEMPLATE.get_code());
+        assert_eq!(1, RST_TEMPLATE.get_version());
+        assert_eq!(RST_TEMPLATE.to_bytes().unwrap(), &[0x70, 0x00, 0x00, 0x00]);
+
+        let ack = Packet::empty_ack(0x1234);
+        assert_eq!(ack.to_bytes().unwrap(), &[0x60, 0x00, 0x12, 0x34]);
+        let ping = Packet::empty_confirmable(7);
+        assert_eq!(ping.to_bytes().unwrap(), &[0x40, 0x00, 0x00, 0x07]);
+    }
+
+    #[test]
+    fn from_bytes_in_larger_storage() {
+        // 40 empty Uri-Path options.
+        let mut buf = [0x00; 44];
+        buf[..5].copy_from_slice(&[0x40, 0x01, 0x00, 0x00, 0xB0]);
+        assert_eq!(
+            MessageError::OptionsLimitExceeded,
+            Packet::from_bytes(&buf).unwrap_err()
+        );
+
+        let packet = Packet::from_bytes_in::<64>(&buf).unwrap();
+        assert_eq!(40, packet.get_options(CoapOption::UriPath).count());
+        assert_eq!(&buf[..], packet.to_bytes().unwrap());
+    }
+
+    #[test]
+    fn validate_bytes() {
+        let with_options = [
+            0x44, 0x01, 0x84, 0x9e, 0x51, 0x55, 0x77, 0xe8, 0xb2, 0x48, 0x69, 0x04, 0x54, 0x65,
+            0x73, 0x74, 0x43, 0x61, 0x3d, 0x31,
+        ];
+        assert_eq!(Ok(()), Packet::validate_bytes(&with_options));
+        let with_payload = [
+            0x64, 0x45, 0x13, 0xFD, 0xD0, 0xE2, 0x4D, 0xAC, 0xFF, 0x48, 0x65, 0x6C, 0x6C, 0x6F,
+        ];
+        assert_eq!(Ok(()), Packet::validate_bytes(&with_payload));
+
+        assert_eq!(
+            Err(MessageError::InvalidPacketLength),
+            Packet::validate_bytes(&[1, 2, 3])
         );
-        assert_eq!(MessageType::Confirmable, p_confirmed.get_type());
-        let p_notconfirmed = Packet::new(
-            MessageType::NonConfirmable,
-            MessageClass::Request(RequestType::Get),
+        assert_eq!(
+            Err(MessageError::InvalidTokenLength),
+            Packet::validate_bytes(&[0x44, 0x01, 0x00, 0x00, 0x51])
+        );
+        assert_eq!(
+            Err(MessageError::InvalidOptionLength),
+            Packet::validate_bytes(&[0x40, 0x01, 0x00, 0x00, 0xBE, 0xFF, 0xFF, 0x00])
+        );
+        assert_eq!(
+            Err(MessageError::InvalidOptionDelta),
+            Packet::validate_bytes(&[0x40, 0x01, 0x00, 0x00, 0xF0])
+        );
+
+        let mut too_many = [0x00; 44];
+        too_many[..5].copy_from_slice(&[0x40, 0x01, 0x00, 0x00, 0xB0]);
+        assert_eq!(
+            Err(MessageError::OptionsLimitExceeded),
+            Packet::validate_bytes(&too_many)
+        );
+    }
+
+    #[test]
+    fn block_value() {
+        assert_eq!(
+            Err(InvalidBlockValue::SizeExponentEncodingError(100)),
+            BlockValue::new(0, false, 100)
+        );
+        assert_eq!(
+            Err(InvalidBlockValue::SizeExponentEncodingError(2048)),
+            BlockValue::new(0, false, 2048)
+        );
+        assert!(matches!(
+            BlockValue::new(70000, false, 16),
+            Err(InvalidBlockValue::TypeBoundsError(_))
+        ));
+
+        let mut buf = [0; 3];
+        let value = BlockValue::new(0, false, 16).unwrap();
+        assert_eq!(&[0x00], value.encode(&mut buf));
+        let value = BlockValue::new(0, false, 64).unwrap();
+        assert_eq!(&[0x02], value.encode(&mut buf));
+        let value = BlockValue::new(300, true, 1024).unwrap();
+        assert_eq!(1024, value.size());
+        assert_eq!(&[0x12, 0xCE], value.encode(&mut buf));
+        let value = BlockValue::new(u16::MAX as usize, false, 16).unwrap();
+        assert_eq!(&[0x0F, 0xFF, 0xF0], value.encode(&mut buf));
+        assert_eq!(Ok(value), BlockValue::try_from(&buf[..]));
+    }
+
+    #[test]
+    fn block2_round_trip() {
+        let options = &[
+            OptionPair {
+                num: CoapOption::ContentFormat.into(),
+                value: &[],
+            },
+            OptionPair {
+                num: CoapOption::Size2.into(),
+                value: &[0x04, 0x00],
+            },
+        ];
+        let mut first = [0; 3];
+        let mut second = [0; 3];
+        let mut block1 = [0; 3];
+        let mut packet = Packet::new(
+            MessageType::Acknowledgement,
+            MessageClass::Response(ResponseType::Content),
             /* version= */ 1,
-            /* message_id= */ 1,
-            /* token= */ &[],
-            /* options= */ &mut Vec::new(),
-            /* payload= */ &[],
+            /* message_id= */ 5117,
+            /* token= */ &[0xD0, 0xE2, 0x4D, 0xAC],
+            /* options= */ &mut Vec::from_slice(options).unwrap(),
+            /* payload= */ &[0x41; 64],
         );
-        assert_eq!(MessageType::NonConfirmable, p_notconfirmed.get_type());
-        let p_reset = Packet::new(
-            MessageType::Reset,
-            MessageClass::Request(RequestType::Get),
+        assert!(packet.get_block2().is_none());
+
+        packet
+            .set_block2(BlockValue::new(0, true, 64).unwrap(), &mut first)
+            .unwrap();
+        packet
+            .set_block2(BlockValue::new(2, false, 64).unwrap(), &mut second)
+            .unwrap();
+        packet
+            .set_block1(BlockValue::new(1, false, 16).unwrap(), &mut block1)
+            .unwrap();
+        let bytes = packet.to_bytes().unwrap();
+
+        let decoded = Packet::from_bytes(&bytes).unwrap();
+        let nums: Vec<u16, 4> = decoded.options.iter().map(|o| o.num).collect();
+        assert_eq!(&[12, 23, 27, 28], nums.as_slice());
+        assert_eq!(
+            Some(Ok(BlockValue {
+                num: 2,
+                more: false,
+                size_exponent: 2,
+            })),
+            decoded.get_block2()
+        );
+        assert_eq!(
+            Some(Ok(BlockValue::new(1, false, 16).unwrap())),
+            decoded.get_block1()
+        );
+    }
+
+    #[test]
+    fn semantically_eq() {
+        let options = &[OptionPair {
+            num: CoapOption::ContentFormat.into(),
+            value: &[],
+        }];
+        let packet = Packet::new(
+            MessageType::Acknowledgement,
+            MessageClass::Response(ResponseType::Content),
             /* version= */ 1,
-            /* message_id= */ 1,
-            /* token= */ &[],
-            /* options= */ &mut Vec::new(),
-            /* payload= */ &[],
+            /* message_id= */ 5117,
+            /* token= */ &[0xD0, 0xE2, 0x4D, 0xAC],
+            /* options= */ &mut Vec::from_slice(options).unwrap(),
+            /* payload= */ "Hello".as_bytes(),
+        );
+        let other = Packet::new(
+            MessageType::Acknowledgement,
+            MessageClass::Response(ResponseType::Content),
+            /* version= */ 1,
+            /* message_id= */ 5118,
+            /* token= */ &[0x01],
+            /* options= */ &mut Vec::from_slice(options).unwrap(),
+            /* payload= */ "Hello".as_bytes(),
+        );
+        assert_ne!(packet, other);
+        assert!(packet.semantically_eq(&other));
+
+        let non_confirmable = Packet::new(
+            MessageType::NonConfirmable,
+            MessageClass::Response(ResponseType::Content),
+            /* version= */ 1,
+            /* message_id= */ 5117,
+            /* token= */ &[0xD0, 0xE2, 0x4D, 0xAC],
+            /* options= */ &mut Vec::from_slice(options).unwrap(),
+            /* payload= */ "Hello".as_bytes(),
+        );
+        assert!(!packet.semantically_eq(&non_confirmable));
+    }
+
+    #[test]
+    fn response_code_groups() {
+        assert!(ResponseType::success_codes().contains(&ResponseType::Content));
+        assert!(ResponseType::client_error_codes().contains(&ResponseType::NotFound));
+        assert!(!ResponseType::success_codes().contains(&ResponseType::NotFound));
+
+        let groups = [
+            (ResponseType::success_codes(), 2),
+            (ResponseType::client_error_codes(), 4),
+            (ResponseType::server_error_codes(), 5),
+        ];
+        for (codes, class) in groups {
+            for code in codes {
+                let byte: u8 = MessageClass::from(*code).into();
+                assert_eq!(class, byte >> 5);
+            }
+        }
+    }
+
+    #[test]
+    fn from_bytes_detailed() {
+        // The Uri-Path option declares 3 bytes but only one follows.
+        let buf = [0x40, 0x01, 0x00, 0x01, 0xb3, 0x48];
+        assert_eq!(
+            DetailedMessageError {
+                kind: MessageError::InvalidOptionLength,
+                offset: 4,
+            },
+            Packet::from_bytes_detailed(&buf).unwrap_err()
+        );
+
+        // The extended length at offset 7 exceeds the buffer.
+        let buf = [0x40, 0x01, 0x00, 0x00, 0xb1, 0x48, 0x0E, 0xFF, 0xFF, 0x00];
+        let err = Packet::from_bytes_detailed(&buf).unwrap_err();
+        assert_eq!(MessageError::InvalidOptionLength, err.kind);
+        assert_eq!(7, err.offset);
+
+        let buf = [0x40, 0x01, 0x00, 0x00, 0xb1, 0x48, 0xF0];
+        let err = Packet::from_bytes_detailed(&buf).unwrap_err();
+        assert_eq!(MessageError::InvalidOptionDelta, err.kind);
+        assert_eq!(6, err.offset);
+
+        let err = Packet::from_bytes_detailed(&[0x44, 0x01, 0x00]).unwrap_err();
+        assert_eq!(MessageError::InvalidPacketLength, err.kind);
+        assert_eq!(0, err.offset);
+    }
+
+    #[test]
+    fn multipart() {
+        let options = &[OptionPair {
+            num: CoapOption::ContentFormat.into(),
+            value: &[62],
+        }];
+        // [0, "Hi", 60, h'A0'], a text/plain part and a CBOR part.
+        let payload = [0x84, 0x00, 0x42, 0x48, 0x69, 0x18, 0x3C, 0x41, 0xA0];
+        let packet = packet_with_payload(options, &payload);
+        assert!(packet.is_multipart());
+
+        let options = &[OptionPair {
+            num: CoapOption::ContentFormat.into(),
+            value: &[60],
+        }];
+        let packet = packet_with_payload(options, &payload);
+        assert!(!packet.is_multipart());
+        assert!(!packet_with_payload(&[], &payload).is_multipart());
+    }
+
+    #[test]
+    fn observe_seq_counter() {
+        let mut counter = ObserveSeqCounter::new();
+        assert_eq!(0, counter.next());
+        assert_eq!(1, counter.next());
+
+        let mut counter = ObserveSeqCounter::starting_at(0xFF_FFFE);
+        assert_eq!(0xFF_FFFE, counter.next());
+        assert_eq!(0xFF_FFFF, counter.next());
+        assert_eq!(0, counter.next());
+    }
+
+    #[test]
+    fn set_observe_seq() {
+        let mut first = [0; 3];
+        let mut second = [0; 3];
+        let mut packet = packet_with_payload(&[], b"22.5");
+        packet.set_observe_seq(0x1_0005, &mut first).unwrap();
+        assert_eq!(
+            &[0x01, 0x00, 0x05],
+            packet.get_first_option(CoapOption::Observe).unwrap().value
+        );
+        packet.set_observe_seq(0x100_0007, &mut second).unwrap();
+        assert_eq!(Some(7), packet.get_observe_value());
+        assert_eq!(1, packet.get_options(CoapOption::Observe).count());
+    }
+
+    #[test]
+    fn validate_for_send() {
+        let options = &[
+            OptionPair {
+                num: CoapOption::UriPath.into(),
+                value: b"a",
+            },
+            OptionPair {
+                num: CoapOption::UriPath.into(),
+                value: b"b",
+            },
+            OptionPair {
+                num: CoapOption::ContentFormat.into(),
+                value: &[60],
+            },
+        ];
+        let packet = packet_with_payload(options, b"x");
+        assert_eq!(Ok(()), packet.validate_for_send());
+        assert_eq!(Ok(packet.to_bytes().unwrap().len()), packet.encoded_len());
+
+        let options = &[
+            OptionPair {
+                num: CoapOption::ContentFormat.into(),
+                value: &[60],
+            },
+            OptionPair {
+                num: CoapOption::ContentFormat.into(),
+                value: &[50],
+            },
+        ];
+        let packet = packet_with_payload(options, b"x");
+        assert_eq!(
+            Err(MessageError::DuplicateOption),
+            packet.validate_for_send()
+        );
+        assert_eq!(
+            MessageError::DuplicateOption,
+            packet.to_bytes_checked().unwrap_err()
+        );
+        assert!(packet.to_bytes().is_ok());
+
+        let options = &[OptionPair {
+            num: CoapOption::ContentFormat.into(),
+            value: &[0, 0, 60],
+        }];
+        let packet = packet_with_payload(options, b"x");
+        assert_eq!(
+            Err(MessageError::InvalidOptionLength),
+            packet.validate_for_send()
+        );
+
+        let options = &[
+            OptionPair {
+                num: CoapOption::RequestTag.into(),
+                value: &[1],
+            },
+            OptionPair {
+                num: CoapOption::RequestTag.into(),
+                value: &[2],
+            },
+        ];
+        let packet = packet_with_payload(options, b"x");
+        assert!(CoapOption::RequestTag.is_repeatable());
+        assert_eq!(Ok(()), packet.validate_for_send());
+
+        // Duplicates are found even if the options aren't sorted.
+        let mut packet = packet_with_payload(&[], b"x");
+        for num in [CoapOption::MaxAge, CoapOption::UriPath, CoapOption::MaxAge] {
+            packet
+                .options
+                .push(OptionPair {
+                    num: num.into(),
+                    value: &[1],
+                })
+                .unwrap();
+        }
+        assert_eq!(
+            Err(MessageError::DuplicateOption),
+            packet.validate_for_send()
+        );
+    }
+
+    #[test]
+    fn transaction_id() {
+        let buf = [
+            0x44, 0x01, 0x84, 0x9e, 0x51, 0x55, 0x77, 0xe8, 0xb2, 0x48, 0x69, 0x04, 0x54, 0x65,
+            0x73, 0x74, 0x43, 0x61, 0x3d, 0x31,
+        ];
+        let packet = Packet::from_bytes(&buf).unwrap();
+        assert_eq!(
+            (0x849e, &[0x51, 0x55, 0x77, 0xe8][..]),
+            packet.transaction_key()
+        );
+
+        let id = TransactionId::try_from(&packet).unwrap();
+        assert_eq!(0x849e, id.message_id);
+        assert_eq!(&[0x51, 0x55, 0x77, 0xe8], id.token.as_slice());
+        assert_eq!(id, TransactionId::try_from(&packet.clone()).unwrap());
+
+        let mut next = buf;
+        next[3] = 0x9f;
+        let other = Packet::from_bytes(&next).unwrap();
+        assert_ne!(id, TransactionId::try_from(&other).unwrap());
+    }
+
+    #[test]
+    fn correlation_mode() {
+        let ack = Packet::empty_ack(42);
+        assert_eq!(CorrelationMode::ByMessageId, ack.correlation_mode());
+
+        let mut response = packet_with_payload(&[], b"22.5");
+        assert_eq!(CorrelationMode::ByToken, response.correlation_mode());
+        response.set_type(MessageType::Acknowledgement);
+        assert_eq!(CorrelationMode::Both, response.correlation_mode());
+    }
+
+    #[test]
+    fn option_diff() {
+        let options = &[
+            OptionPair {
+                num: CoapOption::UriHost.into(),
+                value: b"example.com",
+            },
+            OptionPair {
+                num: CoapOption::UriPath.into(),
+                value: b"temp",
+            },
+            OptionPair {
+                num: CoapOption::ContentFormat.into(),
+                value: &[],
+            },
+        ];
+        let request = packet_with_payload(options, b"x");
+        let options = &[
+            OptionPair {
+                num: CoapOption::UriPath.into(),
+                value: b"temp",
+            },
+            OptionPair {
+                num: CoapOption::UriPath.into(),
+                value: b"now",
+            },
+            OptionPair {
+                num: CoapOption::ContentFormat.into(),
+                value: &[],
+            },
+            OptionPair {
+                num: CoapOption::Accept.into(),
+                value: &[50],
+            },
+        ];
+        let forwarded = packet_with_payload(options, b"x");
+
+        let (added, removed, changed) = request.option_diff(&forwarded);
+        assert_eq!(&[CoapOption::Accept], added.as_slice());
+        assert_eq!(&[CoapOption::UriHost], removed.as_slice());
+        assert_eq!(&[CoapOption::UriPath], changed.as_slice());
+
+        let (added, removed, changed) = request.option_diff(&request);
+        assert!(added.is_empty() && removed.is_empty() && changed.is_empty());
+    }
+
+    #[test]
+    fn from_bytes_with_truncation() {
+        let buf = [
+            0x44, 0x01, 0x84, 0x9e, 0x51, 0x55, 0x77, 0xe8, 0xb2, 0x48, 0x69, 0xFF, 0x31, 0x32,
+        ];
+        let packet = Packet::from_bytes_with_truncation(&buf[..13], buf.len()).unwrap();
+        assert!(packet.is_payload_truncated());
+        assert_eq!(b"1", packet.get_payload());
+
+        let packet = Packet::from_bytes_with_truncation(&buf, buf.len()).unwrap();
+        assert!(!packet.is_payload_truncated());
+        assert_eq!(b"12", packet.get_payload());
+        assert!(!Packet::from_bytes(&buf).unwrap().is_payload_truncated());
+    }
+
+    #[test]
+    fn raw_options() {
+        let buf = [
+            0x44, 0x01, 0x84, 0x9e, 0x51, 0x55, 0x77, 0xe8, 0xb2, 0x48, 0x69, 0x04, 0x54, 0x65,
+            0x73, 0x74, 0x43, 0x61, 0x3d, 0x31,
+        ];
+        let packet = Packet::from_bytes(&buf).unwrap();
+        let options: Vec<OptionPair<'_>, MAX_OPTIONS> = RawOptions::new(&buf[8..], 0)
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(packet.options, options);
+
+        // Uri-Query relative to Uri-Path, followed by the payload.
+        let mut options = RawOptions::new(&[0x43, 0x61, 0x3d, 0x31, 0xFF, 0x01], 11);
+        assert_eq!(
+            Some(Ok(OptionPair {
+                num: CoapOption::UriQuery.into(),
+                value: b"a=1",
+            })),
+            options.next()
+        );
+        assert_eq!(None, options.next());
+
+        let mut options = RawOptions::new(&[0x0F], 0);
+        assert_eq!(Some(Err(MessageError::InvalidOptionLength)), options.next());
+        assert_eq!(None, options.next());
+    }
+
+    #[test]
+    fn is_empty_ack() {
+        let ack = Packet::from_bytes(&[0x60, 0x00, 0x00, 0x2A]).unwrap();
+        assert!(ack.is_empty_ack());
+        assert!(Packet::empty_ack(42).is_empty_ack());
+        assert!(!Packet::empty_reset(42).is_empty_ack());
+
+        let mut response = packet_with_payload(&[], b"22.5");
+        response.set_type(MessageType::Acknowledgement);
+        assert!(!response.is_empty_ack());
+    }
+
+    #[test]
+    fn has_option() {
+        let options = &[OptionPair {
+            num: CoapOption::IfNoneMatch.into(),
+            value: &[],
+        }];
+        let packet = packet_with_payload(options, b"x");
+        assert!(packet.has_option(CoapOption::IfNoneMatch));
+        assert!(!packet.has_option(CoapOption::IfMatch));
+
+        let packet = packet_with_payload(&[], b"x");
+        assert!(!packet.has_option(CoapOption::IfNoneMatch));
+    }
+
+    #[test]
+    fn from_bytes_lenient() {
+        // GET with TKL 10 and a Uri-Path of "Hi".
+        let buf = [
+            0x4A, 0x01, 0x00, 0x2A, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0A,
+            0xb2, 0x48, 0x69,
+        ];
+        assert_eq!(
+            MessageError::InvalidTokenLength,
+            Packet::from_bytes(&buf).unwrap_err()
+        );
+
+        let packet = Packet::from_bytes_lenient(&buf).unwrap();
+        assert!(packet.is_token_nonconformant());
+        assert_eq!(8, packet.get_token_length());
+        assert_eq!(&[1, 2, 3, 4, 5, 6, 7, 8], packet.get_token());
+        assert_eq!(
+            b"Hi",
+            packet.get_first_option(CoapOption::UriPath).unwrap().value
+        );
+
+        assert_eq!(
+            MessageError::InvalidTokenLength,
+            Packet::from_bytes_lenient(&buf[..4]).unwrap_err()
+        );
+        let conformant = Packet::from_bytes_lenient(&[0x40, 0x01, 0x00, 0x2A]).unwrap();
+        assert!(!conformant.is_token_nonconformant());
+    }
+
+    #[test]
+    fn options_slice() {
+        let buf = [
+            0x44, 0x01, 0x84, 0x9e, 0x51, 0x55, 0x77, 0xe8, 0xb2, 0x48, 0x69, 0x04, 0x54, 0x65,
+            0x73, 0x74, 0x43, 0x61, 0x3d, 0x31,
+        ];
+        let packet = Packet::from_bytes(&buf).unwrap();
+        let options = packet.options_slice();
+        assert_eq!(3, options.len());
+        assert!(options.windows(2).all(|pair| pair[0].num <= pair[1].num));
+
+        let query = options.partition_point(|p| p.num < CoapOption::UriQuery.into());
+        assert_eq!(b"a=1", options[query].value);
+    }
+
+    #[test]
+    fn chunks() {
+        let options = &[
+            OptionPair {
+                num: CoapOption::IfNoneMatch.into(),
+                value: &[],
+            },
+            OptionPair {
+                num: CoapOption::UriPath.into(),
+                value: b"temp",
+            },
+            OptionPair {
+                num: CoapOption::ProxyUri.into(),
+                value: &[b'a'; 300],
+            },
+        ];
+        let payload = [0x41; 3000];
+        let packet = packet_with_payload(options, &payload);
+        let mut headers = ChunkHeaders::new();
+        let mut bytes = Vec::<u8, PACKET_MAX_SIZE>::new();
+        let mut count = 0;
+        for chunk in packet.chunks(&mut headers).unwrap() {
+            bytes.extend_from_slice(chunk).unwrap();
+            count += 1;
+        }
+        // Header, 3 option headers, 2 non-empty option values, marker and
+        // payload.
+        assert_eq!(8, count);
+        assert_eq!(packet.to_bytes().unwrap(), bytes);
+
+        let mut long_token = packet.clone();
+        long_token.token = &[0; 9];
+        assert!(matches!(
+            long_token.chunks(&mut headers),
+            Err(MessageError::InvalidTokenLength)
+        ));
+    }
+
+    #[test]
+    fn observe_value_checked() {
+        let packet = packet_with_payload(&[], b"x");
+        assert_eq!(None, packet.get_observe_value_checked());
+
+        let options = &[OptionPair {
+            num: CoapOption::Observe.into(),
+            value: &[0x01, 0x00, 0x05],
+        }];
+        let packet = packet_with_payload(options, b"x");
+        assert_eq!(Some(Ok(0x10005)), packet.get_observe_value_checked());
+
+        let options = &[OptionPair {
+            num: CoapOption::Observe.into(),
+            value: &[0x00, 0x00, 0x00, 0x01],
+        }];
+        let packet = packet_with_payload(options, b"x");
+        assert_eq!(
+            Some(Err(MessageError::InvalidOptionLength)),
+            packet.get_observe_value_checked()
         );
-        assert_eq!(MessageType::Reset, p_reset.get_type());
+        assert_eq!(Some(1), packet.get_observe_value());
     }
 
     #[test]
-    fn test_decode_packet_with_options() {
+    fn builder() {
         let buf = [
             0x44, 0x01, 0x84, 0x9e, 0x51, 0x55, 0x77, 0xe8, 0xb2, 0x48, 0x69, 0x04, 0x54, 0x65,
             0x73, 0x74, 0x43, 0x61, 0x3d, 0x31,
         ];
-        let packet = Packet::from_bytes(&buf);
-        assert!(packet.is_ok());
-        let packet = packet.unwrap();
-        assert_eq!(packet.get_version(), 1);
-        assert_eq!(packet.get_type(), MessageType::Confirmable);
-        assert_eq!(packet.get_token_length(), 4);
-        assert_eq!(packet.get_code(), MessageClass::Request(RequestType::Get));
-        assert_eq!(packet.get_message_id(), 33950);
-        assert_eq!(packet.get_token(), &[0x51, 0x55, 0x77, 0xE8]);
-        assert_eq!(packet.options.len(), 3);
-
-        let mut uri_path_iter = packet.get_options(CoapOption::UriPath);
-        let patch_part1 = uri_path_iter.next();
-        assert!(patch_part1.is_some());
-        assert_eq!(patch_part1.unwrap().value, "Hi".as_bytes());
-        let patch_part2 = uri_path_iter.next();
-        assert!(patch_part2.is_some());
-        assert_eq!(patch_part2.unwrap().value, "Test".as_bytes());
-        assert!(uri_path_iter.next().is_none());
+        let mut builder = Packet::builder();
+        builder
+            .code(MessageClass::Request(RequestType::Get))
+            .message_id(33950)
+            .token(&[0x51, 0x55, 0x77, 0xE8]);
+        builder.add_path_segments(["Hi", "Test"]).unwrap();
+        builder.add_option(CoapOption::UriQuery, b"a=1").unwrap();
+        assert_eq!(&buf[..], builder.build().to_bytes().unwrap());
 
-        let mut uri_query_iter = packet.get_options(CoapOption::UriQuery);
-        let uri_query_item = uri_query_iter.next();
-        assert!(uri_query_item.is_some());
-        assert_eq!(uri_query_item.unwrap().value, "a=1".as_bytes());
-        assert!(uri_query_iter.next().is_none());
+        let ping = Packet::builder().message_id(7).build();
+        assert_eq!(Packet::empty_confirmable(7), ping);
     }
 
     #[test]
-    fn test_decode_packet_with_payload() {
-        let buf = [
-            0x64, 0x45, 0x13, 0xFD, 0xD0, 0xE2, 0x4D, 0xAC, 0xFF, 0x48, 0x65, 0x6C, 0x6C, 0x6F,
+    fn strict_conformance_report() {
+        let packet = packet_with_payload(&[], b"x");
+        assert!(packet.strict_conformance_report().is_empty());
+
+        let options = &[
+            OptionPair {
+                num: CoapOption::ContentFormat.into(),
+                value: &[0, 60],
+            },
+            OptionPair {
+                num: CoapOption::ContentFormat.into(),
+                value: &[50],
+            },
         ];
-        let packet = Packet::from_bytes(&buf);
-        assert!(packet.is_ok());
-        let packet = packet.unwrap();
-        assert_eq!(packet.get_version(), 1);
-        assert_eq!(packet.get_type(), MessageType::Acknowledgement);
-        assert_eq!(packet.get_token_length(), 4);
+        let packet = packet_with_payload(options, b"x");
         assert_eq!(
-            packet.get_code(),
-            MessageClass::Response(ResponseType::Content)
+            &[MessageError::NonMinimalUint, MessageError::DuplicateOption],
+            packet.strict_conformance_report().as_slice()
+        );
+
+        let mut packet = packet_with_payload(&[], &[]);
+        packet.code = MessageClass::Signaling(SignalType::Ping);
+        assert_eq!(
+            &[MessageError::InvalidHeader],
+            packet.strict_conformance_report().as_slice()
         );
-        assert_eq!(packet.message_id, 5117);
-        assert_eq!(packet.get_token(), &[0xD0, 0xE2, 0x4D, 0xAC]);
-        assert_eq!(packet.payload, "Hello".as_bytes().to_vec());
     }
 
     #[test]
-    fn test_encode_packet_with_options() {
+    fn decoded_options() {
         let options = &[
+            OptionPair {
+                num: CoapOption::IfNoneMatch.into(),
+                value: &[],
+            },
             OptionPair {
                 num: CoapOption::UriPath.into(),
-                value: "Hi".as_bytes(),
+                value: b"temp",
             },
             OptionPair {
                 num: CoapOption::UriPath.into(),
-                value: "Test".as_bytes(),
+                value: &[0xFF],
             },
             OptionPair {
-                num: CoapOption::UriQuery.into(),
-                value: "a=1".as_bytes(),
+                num: CoapOption::ContentFormat.into(),
+                value: &[60],
+            },
+            OptionPair {
+                num: CoapOption::Oscore.into(),
+                value: &[0x09],
             },
         ];
-        let packet = Packet::new(
-            MessageType::Confirmable,
-            MessageClass::Request(RequestType::Get),
-            /* version= */ 1,
-            /* message_id= */ 33950,
-            /* token= */ &[0x51, 0x55, 0x77, 0xE8],
-            /* options= */ &mut Vec::from_slice(options).unwrap(),
-            /* payload= */ &[],
+        let packet = packet_with_payload(options, &[]);
+        let decoded: Vec<(CoapOption, DecodedValue), 5> = packet.decoded_options().collect();
+        assert_eq!(
+            decoded,
+            [
+                (CoapOption::IfNoneMatch, DecodedValue::Empty),
+                (CoapOption::Oscore, DecodedValue::Opaque(&[0x09])),
+                (CoapOption::UriPath, DecodedValue::Str("temp")),
+                (CoapOption::UriPath, DecodedValue::Opaque(&[0xFF])),
+                (CoapOption::ContentFormat, DecodedValue::Uint(60)),
+            ]
         );
+    }
+
+    #[test]
+    fn parse_coap_uri() {
+        let mut port = [0; 2];
+        let mut decode = [0; 16];
+        let mut options = Vec::new();
+        super::parse_coap_uri(
+            "coap://host:5684/a/b?x=1&y",
+            &mut port,
+            &mut decode,
+            &mut options,
+        )
+        .unwrap();
         assert_eq!(
-            packet.to_bytes().unwrap(),
-            &[
-                0x44, 0x01, 0x84, 0x9e, 0x51, 0x55, 0x77, 0xe8, 0xb2, 0x48, 0x69, 0x04, 0x54, 0x65,
-                0x73, 0x74, 0x43, 0x61, 0x3d, 0x31
+            options,
+            [
+                OptionPair {
+                    num: CoapOption::UriHost.into(),
+                    value: b"host",
+                },
+                OptionPair {
+                    num: CoapOption::UriPort.into(),
+                    value: &[0x16, 0x34],
+                },
+                OptionPair {
+                    num: CoapOption::UriPath.into(),
+                    value: b"a",
+                },
+                OptionPair {
+                    num: CoapOption::UriPath.into(),
+                    value: b"b",
+                },
+                OptionPair {
+                    num: CoapOption::UriQuery.into(),
+                    value: b"x=1",
+                },
+                OptionPair {
+                    num: CoapOption::UriQuery.into(),
+                    value: b"y",
+                },
             ]
         );
     }
 
     #[test]
-    fn test_encode_packet_with_payload() {
+    fn parse_coap_uri_default_port() {
+        let mut port = [0; 2];
+        let mut options = Vec::new();
+        super::parse_coap_uri("coaps://[::1]:5684/", &mut port, &mut [], &mut options).unwrap();
+        assert!(options.is_empty());
+        let mut port = [0; 2];
+        let mut options = Vec::new();
+        super::parse_coap_uri("coap://192.0.2.1/a", &mut port, &mut [], &mut options).unwrap();
+        assert_eq!(
+            options,
+            [OptionPair {
+                num: CoapOption::UriPath.into(),
+                value: b"a",
+            }]
+        );
+        let mut port = [0; 2];
+        let mut options = Vec::new();
+        super::parse_coap_uri("CoAP://192.0.2.1/a?", &mut port, &mut [], &mut options).unwrap();
+        assert_eq!(
+            options,
+            [OptionPair {
+                num: CoapOption::UriPath.into(),
+                value: b"a",
+            }]
+        );
+
+        for uri in [
+            "http://host/a",
+            "coap://host/a%2",
+            "coap://host/a#b",
+            "coap:///a",
+            "coap://host:x/",
+        ] {
+            let mut port = [0; 2];
+            let mut decode = [0; 8];
+            let mut options = Vec::new();
+            assert_eq!(
+                Err(MessageError::InvalidUri),
+                super::parse_coap_uri(uri, &mut port, &mut decode, &mut options)
+            );
+        }
+    }
+
+    #[test]
+    fn parse_coap_uri_percent_encoded() {
+        let mut port = [0; 2];
+        let mut decode = [0; 16];
+        let mut options = Vec::new();
+        super::parse_coap_uri(
+            "coap://h%6Fst/a%2Fb/c?x=%31",
+            &mut port,
+            &mut decode,
+            &mut options,
+        )
+        .unwrap();
+        let values: Vec<&[u8], 4> = options.iter().map(|option| option.value).collect();
+        assert_eq!(values, [&b"host"[..], b"a/b", b"c", b"x=1"]);
+
+        let mut port = [0; 2];
+        let mut decode = [0; 1];
+        let mut options = Vec::new();
+        options
+            .push(OptionPair {
+                num: CoapOption::IfNoneMatch.into(),
+                value: &[],
+            })
+            .unwrap();
+        assert_eq!(
+            Err(MessageError::BufferTooSmall),
+            super::parse_coap_uri("coap://host/a/%20%20", &mut port, &mut decode, &mut options)
+        );
+        assert_eq!(1, options.len());
+    }
+
+    #[test]
+    fn crc16() {
         let packet = Packet::new(
             MessageType::Acknowledgement,
             MessageClass::Response(ResponseType::Content),
@@ -1078,250 +4813,650 @@ mod test {
             /* options= */ &mut Vec::new(),
             /* payload= */ "Hello".as_bytes(),
         );
+        assert_eq!(Ok(0xF827), packet.crc16());
+
+        let buf = [
+            0x44, 0x01, 0x84, 0x9e, 0x51, 0x55, 0x77, 0xe8, 0xb2, 0x48, 0x69, 0x04, 0x54, 0x65,
+            0x73, 0x74, 0x43, 0x61, 0x3d, 0x31,
+        ];
+        let packet = Packet::from_bytes(&buf).unwrap();
+        assert_eq!(Ok(0x3C1D), packet.crc16());
+
+        let mut unsorted = packet.clone();
+        unsorted.options.swap(0, 2);
+        assert_eq!(Err(MessageError::InvalidOptionDelta), unsorted.crc16());
+        let mut unknown = packet.clone();
+        unknown.code = MessageClass::Request(RequestType::UnKnown);
+        assert_eq!(Err(MessageError::InvalidHeader), unknown.crc16());
+    }
+
+    #[test]
+    fn to_non_confirmable() {
+        let buf = [
+            0x44, 0x01, 0x84, 0x9e, 0x51, 0x55, 0x77, 0xe8, 0xb2, 0x48, 0x69, 0x04, 0x54, 0x65,
+            0x73, 0x74, 0x43, 0x61, 0x3d, 0x31,
+        ];
+        let packet = Packet::from_bytes(&buf).unwrap();
+        let non = packet.to_non_confirmable();
+        assert_eq!(MessageType::NonConfirmable, non.get_type());
+        assert_eq!(packet.get_version(), non.get_version());
+        assert_eq!(packet.get_code(), non.get_code());
+        assert_eq!(packet.get_message_id(), non.get_message_id());
+        assert_eq!(packet.get_token(), non.get_token());
+        assert_eq!(packet.options, non.options);
+        assert_eq!(packet.get_payload(), non.get_payload());
+        assert_ne!(packet, non);
+        assert!(!packet.semantically_eq(&non));
+        assert_eq!(&[0x54, 0x01], &non.to_bytes().unwrap()[..2]);
+    }
+
+    #[test]
+    fn maximal_extended_fields() {
+        // Extended delta of 255 + 13.
+        let buf = [0x40, 0x01, 0x00, 0x00, 0xD0, 0xFF];
+        let packet = Packet::from_bytes(&buf).unwrap();
+        assert_eq!(268, packet.options[0].num);
+
+        let cases: [(&[u8], MessageError); 5] = [
+            // Extended delta of 65535 + 269.
+            (
+                &[0x40, 0x01, 0x00, 0x00, 0xE0, 0xFF, 0xFF],
+                MessageError::InvalidOptionDelta,
+            ),
+            // Option number 65535 followed by a delta of 1.
+            (
+                &[0x40, 0x01, 0x00, 0x00, 0xE0, 0xFE, 0xF2, 0x10],
+                MessageError::InvalidOptionDelta,
+            ),
+            // Extended length of 65535 + 269 on a short buffer.
+            (
+                &[0x40, 0x01, 0x00, 0x00, 0x0E, 0xFF, 0xFF, 0x00],
+                MessageError::InvalidOptionLength,
+            ),
+            (
+                &[0x40, 0x01, 0x00, 0x00, 0xEE, 0x00, 0x00, 0xFF, 0xFF],
+                MessageError::InvalidOptionLength,
+            ),
+            // Extended length of 255 + 13 on a short buffer.
+            (
+                &[0x40, 0x01, 0x00, 0x00, 0xDD, 0xFF, 0xFF, 0x00],
+                MessageError::InvalidOptionLength,
+            ),
+        ];
+        for (buf, err) in cases {
+            assert_eq!(err, Packet::from_bytes(buf).unwrap_err());
+            assert_eq!(Err(err), Packet::validate_bytes(buf));
+        }
+    }
+
+    #[test]
+    fn packet_decoder() {
+        let request = [
+            0x44, 0x01, 0x84, 0x9e, 0x51, 0x55, 0x77, 0xe8, 0xb2, 0x48, 0x69, 0x04, 0x54, 0x65,
+            0x73, 0x74, 0x43, 0x61, 0x3d, 0x31,
+        ];
+        let response = [
+            0x64, 0x45, 0x13, 0xFD, 0xD0, 0xE2, 0x4D, 0xAC, 0xFF, 0x48, 0x65, 0x6C, 0x6C, 0x6F,
+        ];
+        let mut truncated = request;
+        truncated[16] = 0x4f;
+        let mut decoder = PacketDecoder::new();
+
+        let packet = decoder.decode(&request).unwrap();
+        assert_eq!(&Packet::from_bytes(&request).unwrap(), packet);
+        assert_eq!(3, packet.options().len());
+
+        let packet = decoder.decode(&response).unwrap();
+        assert_eq!(&Packet::from_bytes(&response).unwrap(), packet);
+        assert_eq!(0, packet.options().len());
+        assert_eq!(b"Hello", packet.get_payload());
+
+        assert_eq!(
+            MessageError::InvalidPacketLength,
+            decoder.decode(&request[..3]).unwrap_err()
+        );
+        assert_eq!(Packet::empty_confirmable(0), decoder.packet);
+
+        assert!(decoder.decode(&truncated).is_err());
+        assert_eq!(Packet::empty_confirmable(0), decoder.packet);
+    }
+
+    #[test]
+    fn join_option_values() {
+        let options = &[
+            OptionPair {
+                num: CoapOption::UriPath.into(),
+                value: b"a",
+            },
+            OptionPair {
+                num: CoapOption::UriPath.into(),
+                value: b"bc",
+            },
+            OptionPair {
+                num: CoapOption::UriPath.into(),
+                value: b"d",
+            },
+            OptionPair {
+                num: CoapOption::UriQuery.into(),
+                value: b"x=1",
+            },
+        ];
+        let packet = packet_with_payload(options, &[]);
+        let joined: Vec<u8, 16> = packet
+            .join_option_values(CoapOption::UriPath, b'/')
+            .unwrap();
+        assert_eq!(b"a/bc/d", joined.as_slice());
+        let joined: Vec<u8, 16> = packet
+            .join_option_values(CoapOption::UriHost, b'/')
+            .unwrap();
+        assert!(joined.is_empty());
         assert_eq!(
-            packet.to_bytes().unwrap(),
-            &[0x64, 0x45, 0x13, 0xFD, 0xD0, 0xE2, 0x4D, 0xAC, 0xFF, 0x48, 0x65, 0x6C, 0x6C, 0x6F]
+            MessageError::BufferTooSmall,
+            packet
+                .join_option_values::<5>(CoapOption::UriPath, b'/')
+                .unwrap_err()
         );
     }
 
     #[test]
-    fn test_encode_decode_content_format() {
+    fn empty_uint_option_is_zero() {
+        let options = &[
+            OptionPair {
+                num: CoapOption::Observe.into(),
+                value: &[],
+            },
+            OptionPair {
+                num: CoapOption::MaxAge.into(),
+                value: &[],
+            },
+        ];
+        let packet = packet_with_payload(options, &[]);
+        assert_eq!(Some(0), packet.get_observe_value());
+        assert_eq!(Some(Ok(0)), packet.option_u32(CoapOption::MaxAge));
+        assert_eq!(Some(Ok(0)), packet.option_u8(CoapOption::MaxAge));
+        assert_eq!(Ok(()), packet.validate_for_send());
+
+        let mut buf = [0; 8];
+        assert!(encode_uint(0, &mut buf).unwrap().is_empty());
+    }
+
+    #[test]
+    fn options_in_range() {
+        let options = &[
+            OptionPair {
+                num: CoapOption::UriHost.into(),
+                value: b"proxy",
+            },
+            OptionPair {
+                num: CoapOption::ProxyUri.into(),
+                value: b"coap://host/a",
+            },
+            OptionPair {
+                num: CoapOption::ProxyScheme.into(),
+                value: b"coap",
+            },
+            OptionPair {
+                num: CoapOption::Size1.into(),
+                value: &[0x10],
+            },
+        ];
+        let packet = packet_with_payload(options, &[]);
+        let nums: Vec<u16, 4> = packet.options_in_range(35, 39).map(|p| p.num).collect();
+        assert_eq!(&[35, 39], nums.as_slice());
+        assert_eq!(4, packet.options_in_range(0, u16::MAX).count());
+        assert_eq!(0, packet.options_in_range(36, 38).count());
+        assert_eq!(0, packet.options_in_range(39, 35).count());
+    }
+
+    #[test]
+    fn fits_mtu() {
+        let buf = [
+            0x44, 0x01, 0x84, 0x9e, 0x51, 0x55, 0x77, 0xe8, 0xb2, 0x48, 0x69, 0x04, 0x54, 0x65,
+            0x73, 0x74, 0x43, 0x61, 0x3d, 0x31,
+        ];
+        let packet = Packet::from_bytes(&buf).unwrap();
+        assert!(packet.fits_mtu(128));
+        assert!(packet.fits_mtu(20));
+        assert!(!packet.fits_mtu(19));
+
+        // 26 bytes of overhead leave 230 bytes, enough for 128 byte blocks.
+        assert_eq!(Some(3), packet.recommended_block_szx(256));
+        assert_eq!(Some(6), packet.recommended_block_szx(1280));
+        assert_eq!(Some(0), packet.recommended_block_szx(42));
+        assert_eq!(None, packet.recommended_block_szx(41));
+        assert_eq!(None, packet.recommended_block_szx(20));
+
+        // An existing Block2 option is replaced, not counted twice.
+        let mut block = [0; 3];
+        let mut options: Vec<OptionPair, MAX_OPTIONS> = packet.options().cloned().collect();
+        options
+            .push(OptionPair {
+                num: CoapOption::Block2.into(),
+                value: BlockValue::new(2, true, 64).unwrap().encode(&mut block),
+            })
+            .unwrap();
+        let mut with_block = packet.clone();
+        with_block.options = options;
+        assert_eq!(Some(0), with_block.recommended_block_szx(42));
+    }
+
+    #[test]
+    fn if_match() {
         let options = &[OptionPair {
-            num: CoapOption::ContentFormat.into(),
-            value: &u16::try_from(usize::from(ContentFormat::TextPlain))
-                .unwrap()
-                .to_be_bytes(),
+            num: CoapOption::IfMatch.into(),
+            value: &[],
         }];
-        let packet = Packet::new(
-            MessageType::NonConfirmable,
-            MessageClass::Request(RequestType::Get),
-            /* version= */ 1,
-            /* message_id= */ 5117,
-            /* token= */ &[0xD0, 0xE2, 0x4D, 0xAC],
-            /* options= */ &mut Vec::from_slice(options).unwrap(),
-            /* payload= */ "Hello".as_bytes(),
+        let packet = packet_with_payload(options, &[]);
+        assert!(packet.if_match_any());
+        assert_eq!(0, packet.if_match_etags().count());
+
+        let options = &[
+            OptionPair {
+                num: CoapOption::IfMatch.into(),
+                value: &[0x5A, 0x01],
+            },
+            OptionPair {
+                num: CoapOption::IfMatch.into(),
+                value: &[],
+            },
+        ];
+        let packet = packet_with_payload(options, &[]);
+        assert!(packet.if_match_any());
+        let etags: Vec<&[u8], 2> = packet.if_match_etags().collect();
+        assert_eq!(&[&[0x5A, 0x01][..]], etags.as_slice());
+
+        let packet = packet_with_payload(&options[..1], &[]);
+        assert!(!packet.if_match_any());
+        assert_eq!(1, packet.if_match_etags().count());
+    }
+
+    #[test]
+    fn from_bytes_limited() {
+        let buf = [
+            0x44, 0x01, 0x84, 0x9e, 0x51, 0x55, 0x77, 0xe8, 0xb2, 0x48, 0x69, 0x04, 0x54, 0x65,
+            0x73, 0x74, 0x43, 0x61, 0x3d, 0x31,
+        ];
+        assert!(Packet::from_bytes_limited(&buf, 12).is_ok());
+        assert_eq!(
+            MessageError::InvalidOptionLength,
+            Packet::from_bytes_limited(&buf, 11).unwrap_err()
+        );
+
+        // Two 600 byte Proxy-Uri options followed by a payload.
+        let mut buf = [0x41; 1213];
+        buf[..8].copy_from_slice(&[0x40, 0x01, 0x00, 0x00, 0xDE, 0x16, 0x01, 0x4B]);
+        buf[608..611].copy_from_slice(&[0x0E, 0x01, 0x4B]);
+        buf[1211] = 0xFF;
+        let packet = Packet::from_bytes(&buf).unwrap();
+        assert_eq!(2, packet.get_options(CoapOption::ProxyUri).count());
+        assert_eq!(
+            MessageError::InvalidOptionLength,
+            Packet::from_bytes_limited(&buf, 256).unwrap_err()
+        );
+        assert!(Packet::from_bytes_limited(&buf, 1207).is_ok());
+
+        // The cap is hit before the invalid option delta that follows.
+        let mut invalid = buf;
+        invalid[608] = 0xF0;
+        assert_eq!(
+            MessageError::InvalidOptionDelta,
+            Packet::from_bytes(&invalid).unwrap_err()
+        );
+        assert_eq!(
+            MessageError::InvalidOptionLength,
+            Packet::from_bytes_limited(&invalid, 256).unwrap_err()
         );
+    }
+
+    #[test]
+    fn reset_for() {
+        let buf = [
+            0x44, 0x01, 0x84, 0x9e, 0x51, 0x55, 0x77, 0xe8, 0xb2, 0x48, 0x69, 0x04, 0x54, 0x65,
+            0x73, 0x74, 0x43, 0x61, 0x3d, 0x31,
+        ];
+        let received = Packet::from_bytes(&buf).unwrap();
+        let reset = Packet::reset_for(&received);
+        assert_eq!(MessageType::Reset, reset.get_type());
+        assert_eq!(MessageClass::Empty, reset.get_code());
+        assert_eq!(33950, reset.get_message_id());
+        assert_eq!(reset.to_bytes().unwrap(), &[0x70, 0x00, 0x84, 0x9e]);
+    }
+
+    #[test]
+    fn get_etags_checked() {
+        let options = &[
+            OptionPair {
+                num: CoapOption::ETag.into(),
+                value: &[0x01, 0x02],
+            },
+            OptionPair {
+                num: CoapOption::ETag.into(),
+                value: &[0xAA; 9],
+            },
+        ];
+        let packet = packet_with_payload(options, &[]);
+        let mut etags = packet.get_etags_checked();
+        assert_eq!(Some(Ok(&[0x01, 0x02][..])), etags.next());
+        assert_eq!(Some(Err(MessageError::InvalidOptionLength)), etags.next());
+        assert_eq!(None, etags.next());
+    }
+
+    #[test]
+    fn option_i32() {
+        let vendor = CoapOption::Unknown(65000);
+        let decode = |value: &[u8]| {
+            let options = &[OptionPair {
+                num: vendor.into(),
+                value,
+            }];
+            packet_with_payload(options, &[]).option_i32(vendor)
+        };
+        assert_eq!(Some(-1), decode(&[0xFF]));
+        assert_eq!(Some(-32768), decode(&[0x80, 0x00]));
+        assert_eq!(Some(127), decode(&[0x7F]));
+        assert_eq!(Some(0), decode(&[]));
+        assert_eq!(None, decode(&[0; 5]));
+
+        let mut buf = [0; 4];
+        for value in [0, 1, -1, 127, 128, -128, -129, -32768, i32::MIN, i32::MAX] {
+            let encoded = encode_int(value, &mut buf).unwrap();
+            assert_eq!(Some(value), decode(encoded));
+        }
+        assert_eq!(&[0xFF], encode_int(-1, &mut buf).unwrap());
+        assert_eq!(&[0x00, 0x80], encode_int(128, &mut buf).unwrap());
+        assert_eq!(&[0x80, 0x00], encode_int(-32768, &mut buf).unwrap());
         assert_eq!(
-            ContentFormat::TextPlain,
-            ContentFormat::try_from(packet.get_content_format_value().unwrap() as usize)
-                .ok()
-                .unwrap()
+            MessageError::BufferTooSmall,
+            encode_int(i32::MIN, &mut [0; 3]).unwrap_err()
         );
     }
 
     #[test]
-    fn test_encode_decode_content_format_without_msb() {
-        let options = &[OptionPair {
-            num: CoapOption::ContentFormat.into(),
-            value: &u16::try_from(usize::from(ContentFormat::ApplicationJSON))
-                .unwrap()
-                .to_be_bytes(),
-        }];
+    fn options_eq() {
+        let path = |value| OptionPair {
+            num: CoapOption::UriPath.into(),
+            value,
+        };
+        let query = OptionPair {
+            num: CoapOption::UriQuery.into(),
+            value: b"a=1",
+        };
+        let packet = packet_with_payload(&[path(b"a"), path(b"b"), query], &[]);
+        let reordered = packet_with_payload(&[query, path(b"a"), path(b"b")], &[]);
+        assert!(packet.options_eq(&reordered));
+
+        let swapped = packet_with_payload(&[path(b"b"), path(b"a"), query], &[]);
+        assert!(!packet.options_eq(&swapped));
+        let fewer = packet_with_payload(&[path(b"a"), path(b"b")], &[]);
+        assert!(!packet.options_eq(&fewer));
+    }
+
+    fn round_trip_extended_token(len: usize) {
+        let token = [0x5A; 300];
         let packet = Packet::new(
-            MessageType::NonConfirmable,
+            MessageType::Confirmable,
             MessageClass::Request(RequestType::Get),
             /* version= */ 1,
-            /* message_id= */ 5117,
-            /* token= */ &[0xD0, 0xE2, 0x4D, 0xAC],
-            /* options= */ &mut Vec::from_slice(options).unwrap(),
+            /* message_id= */ 1,
+            /* token= */ &token[..len],
+            /* options= */ &mut Vec::new(),
             /* payload= */ "Hello".as_bytes(),
         );
         assert_eq!(
-            ContentFormat::ApplicationJSON,
-            ContentFormat::try_from(packet.get_content_format_value().unwrap() as usize)
-                .ok()
-                .unwrap()
+            MessageError::InvalidTokenLength,
+            packet.to_bytes().unwrap_err()
+        );
+        let bytes = packet.to_bytes_extended_token().unwrap();
+        assert_eq!(Ok(bytes.len()), packet.encoded_len());
+        assert_eq!(
+            MessageError::InvalidTokenLength,
+            Packet::from_bytes(&bytes).unwrap_err()
         );
+        let decoded = Packet::from_bytes_extended_token(&bytes).unwrap();
+        assert_eq!(bytes, decoded.to_bytes_extended_token().unwrap());
+        assert_eq!(len, decoded.get_token().len());
+        assert_eq!(b"Hello", decoded.get_payload());
     }
 
     #[test]
-    fn test_encode_decode_content_format_with_msb() {
-        let options = &[OptionPair {
-            num: CoapOption::ContentFormat.into(),
-            value: &u16::try_from(usize::from(ContentFormat::ApplicationSensmlXML))
+    fn extended_token_length() {
+        round_trip_extended_token(13);
+        round_trip_extended_token(270);
+
+        // TKL 13 with an extension of 0 is a 13 byte token.
+        let mut buf = [
+            0x4D, 0x01, 0x00, 0x01, 0x00, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        ];
+        assert_eq!(
+            13,
+            Packet::from_bytes_extended_token(&buf)
                 .unwrap()
-                .to_be_bytes(),
-        }];
-        let packet = Packet::new(
-            MessageType::NonConfirmable,
-            MessageClass::Request(RequestType::Get),
-            /* version= */ 1,
-            /* message_id= */ 5117,
-            /* token= */ &[0xD0, 0xE2, 0x4D, 0xAC],
-            /* options= */ &mut Vec::from_slice(options).unwrap(),
-            /* payload= */ "Hello".as_bytes(),
+                .get_token()
+                .len()
         );
+        buf[4] = 1;
         assert_eq!(
-            ContentFormat::ApplicationSensmlXML,
-            ContentFormat::try_from(packet.get_content_format_value().unwrap() as usize)
-                .ok()
-                .unwrap()
+            MessageError::InvalidTokenLength,
+            Packet::from_bytes_extended_token(&buf).unwrap_err()
+        );
+        assert_eq!(
+            MessageError::InvalidTokenLength,
+            Packet::from_bytes_extended_token(&[0x4F, 0x01, 0x00, 0x01]).unwrap_err()
         );
     }
 
     #[test]
-    fn test_decode_empty_content_format() {
-        let packet = Packet::new(
-            MessageType::NonConfirmable,
-            MessageClass::Request(RequestType::Get),
-            /* version= */ 1,
-            /* message_id= */ 5117,
-            /* token= */ &[0xD0, 0xE2, 0x4D, 0xAC],
-            /* options= */ &mut Vec::new(),
-            /* payload= */ "Hello".as_bytes(),
-        );
-        assert!(packet.get_content_format_value().is_none());
+    fn collect_options() {
+        let buf = [
+            0x44, 0x01, 0x84, 0x9e, 0x51, 0x55, 0x77, 0xe8, 0xb2, 0x48, 0x69, 0x04, 0x54, 0x65,
+            0x73, 0x74, 0x43, 0x61, 0x3d, 0x31,
+        ];
+        let packet = Packet::from_bytes(&buf).unwrap();
+        let mut out: [&[u8]; 4] = [&[]; 4];
+        assert_eq!(2, packet.collect_options(CoapOption::UriPath, &mut out));
+        assert_eq!([&b"Hi"[..], b"Test", b"", b""], out);
+
+        let mut out: [&[u8]; 1] = [&[]; 1];
+        assert_eq!(1, packet.collect_options(CoapOption::UriPath, &mut out));
+        assert_eq!([&b"Hi"[..]], out);
     }
 
     #[test]
-    fn option() {
-        for i in 0..512 {
-            assert_eq!(i, CoapOption::from(i).into());
-        }
+    fn remove_options() {
+        let buf = [
+            0x44, 0x01, 0x84, 0x9e, 0x51, 0x55, 0x77, 0xe8, 0xb2, 0x48, 0x69, 0x04, 0x54, 0x65,
+            0x73, 0x74, 0x43, 0x61, 0x3d, 0x31,
+        ];
+        let mut packet = Packet::from_bytes(&buf).unwrap();
+        assert_eq!(1, packet.remove_options(CoapOption::UriQuery));
+        assert_eq!(0, packet.remove_options(CoapOption::UriQuery));
+        let options: Vec<(CoapOption, &[u8]), 2> = packet.typed_options().collect();
+        assert_eq!(
+            options,
+            [
+                (CoapOption::UriPath, b"Hi".as_slice()),
+                (CoapOption::UriPath, b"Test".as_slice()),
+            ]
+        );
+        assert_eq!(&buf[..16], packet.to_bytes().unwrap());
     }
 
     #[test]
-    fn content_format() {
-        for i in 0..512 {
-            if let Ok(o) = ContentFormat::try_from(i) {
-                assert_eq!(i, o.into());
-            }
-        }
+    fn minimal_option_header() {
+        let value = [0x41; 269];
+        let header = |num: u16, len: usize| {
+            let options = &[OptionPair {
+                num,
+                value: &value[..len],
+            }];
+            let bytes = packet_with_payload(options, &[]).to_bytes().unwrap();
+            Vec::<u8, 5>::from_slice(&bytes[4..bytes.len() - len]).unwrap()
+        };
+        assert_eq!(&[0xC0], header(12, 0).as_slice());
+        assert_eq!(&[0xD0, 0x00], header(13, 0).as_slice());
+        assert_eq!(&[0xD0, 0xFF], header(268, 0).as_slice());
+        assert_eq!(&[0xE0, 0x00, 0x00], header(269, 0).as_slice());
+        assert_eq!(&[0x1C], header(1, 12).as_slice());
+        assert_eq!(&[0x1D, 0x00], header(1, 13).as_slice());
+        assert_eq!(&[0x1D, 0xFF], header(1, 268).as_slice());
+        assert_eq!(&[0x1E, 0x00, 0x00], header(1, 269).as_slice());
+        assert_eq!(&[0xDE, 0xFF, 0x00, 0x00], header(268, 269).as_slice());
     }
 
     #[test]
-    fn observe_option() {
-        for i in 0..8 {
-            if let Ok(o) = ObserveOption::try_from(i) {
-                assert_eq!(i, o.into());
-            }
-        }
+    fn signaling_csm() {
+        assert_eq!(
+            MessageClass::Signaling(SignalType::Pong),
+            MessageClass::from(0xE3)
+        );
+        assert_eq!(0xE5, u8::from(MessageClass::Signaling(SignalType::Abort)));
+
+        // 7.01 CSM with Max-Message-Size 1152 and Block-Wise-Transfer, over TCP.
+        let frame = [0x40, 0xE1, 0x22, 0x04, 0x80, 0x20];
+        let mut decoder = crate::tcp::TcpDecoder::new();
+        decoder.push(&frame).unwrap();
+        let packet = decoder.next().unwrap().unwrap();
+        assert_eq!(MessageClass::Signaling(SignalType::Csm), packet.get_code());
+        assert_eq!(Some(1152), packet.csm_max_message_size());
+        assert!(packet.csm_block_wise_transfer());
+        assert!(!packet.custody());
+        assert_eq!(None, packet.abort_bad_csm_option());
     }
 
     #[test]
-    fn options() {
+    fn signaling_release() {
         let options = &[
             OptionPair {
-                num: CoapOption::UriHost.into(),
-                value: &[0],
-            },
-            OptionPair {
-                num: CoapOption::UriPath.into(),
-                value: &[1],
+                num: 2,
+                value: b"coap+tcp://[::1]",
             },
             OptionPair {
-                num: CoapOption::ETag.into(),
-                value: &[2],
+                num: 4,
+                value: &[30],
             },
         ];
         let packet = Packet::new(
-            MessageType::NonConfirmable,
-            MessageClass::Request(RequestType::Get),
+            MessageType::Confirmable,
+            MessageClass::Signaling(SignalType::Release),
             /* version= */ 1,
-            /* message_id= */ 5117,
-            /* token= */ &[0xD0, 0xE2, 0x4D, 0xAC],
+            /* message_id= */ 0,
+            /* token= */ &[],
             /* options= */ &mut Vec::from_slice(options).unwrap(),
-            /* payload= */ "Hello".as_bytes(),
+            /* payload= */ &[],
         );
-        assert_eq!(3, packet.options().len());
-
-        let bytes = packet.to_bytes().unwrap();
-        let pp = Packet::from_bytes(&bytes).unwrap();
-        assert_eq!(3, pp.options().len());
+        assert_eq!(
+            Some("coap+tcp://[::1]"),
+            packet.release_alternative_address()
+        );
+        assert_eq!(Some(30), packet.release_hold_off());
+        assert_eq!(None, packet.csm_max_message_size());
     }
 
-    // #[test]
-    // fn test_option_u32_format() {
-    //     let options = &[
-    //         OptionPair {
-    //             num: CoapOption::Observe.into(),
-    //             value: [],
-    //         },
-    //         OptionPair {
-    //             num: CoapOption::Observe.into(),
-    //             value: &[1],
-    //         },
-    //         OptionPair {
-    //             num: CoapOption::Observe.into(),
-    //             value: &[2],
-    //         },
-    //     ];
-    //     let packet = Packet::new(
-    //         MessageType::NonConfirmable,
-    //         MessageClass::Request(RequestType::Get),
-    //         /* version= */ 1,
-    //         /* message_id= */ 5117,
-    //         /* token= */ &[0xD0, 0xE2, 0x4D, 0xAC],
-    //         /* options= */ &mut Vec::from_slice(options).unwrap(),
-    //         /* payload= */ "Hello".as_bytes(),
-    //     );
-    //     let values = vec![0, 100, 1000, 10000, u32::MAX];
-    //     let expected = values.iter().map(|&x| Ok(OptionValueU32(x))).collect();
-    //     assert_eq!(actual, Some(expected));
-    // }
+    #[test]
+    fn payload_with_format() {
+        let options = &[OptionPair {
+            num: CoapOption::ContentFormat.into(),
+            value: &[50],
+        }];
+        let packet = packet_with_payload(options, br#"{"t":22.5}"#);
+        assert_eq!(
+            (Some(ContentFormat::ApplicationJSON), &br#"{"t":22.5}"#[..]),
+            packet.payload_with_format()
+        );
+        assert_eq!(
+            (None, &b"22.5"[..]),
+            packet_with_payload(&[], b"22.5").payload_with_format()
+        );
+    }
 
-    // #[test]
-    // fn test_option_utf8_format() {
-    //     let mut p = Packet::new();
-    //     let option_key = CoapOption::UriPath;
-    //     let values = vec!["", "simple", "unicode 😁 stuff"];
-    //     for &value in &values {
-    //         p.add_option_as(option_key, OptionValueString(value.to_owned()));
-    //     }
-    //     let expected = values
-    //         .iter()
-    //         .map(|&x| Ok(OptionValueString(x.to_owned())))
-    //         .collect();
-    //     let actual = p.get_options_as::<OptionValueString>(option_key);
-    //     assert_eq!(actual, Some(expected));
-    // }
+    #[test]
+    fn resort_options() {
+        let buf = [
+            0x44, 0x01, 0x84, 0x9e, 0x51, 0x55, 0x77, 0xe8, 0xb2, 0x48, 0x69, 0x04, 0x54, 0x65,
+            0x73, 0x74, 0x43, 0x61, 0x3d, 0x31,
+        ];
+        let mut packet = Packet::from_bytes(&buf).unwrap();
+        let query = packet.options.pop().unwrap();
+        packet.options.insert(0, query).unwrap();
+        assert_eq!(
+            MessageError::InvalidOptionDelta,
+            packet.to_bytes().unwrap_err()
+        );
+        packet.resort_options();
+        assert_eq!(&buf[..], packet.to_bytes().unwrap());
+    }
 
     #[test]
-    fn observe_none() {
+    fn options_from_pairs() {
+        let mut options = Vec::new();
+        Packet::options_from_pairs(
+            &[
+                (CoapOption::UriQuery, b"a=1"),
+                (CoapOption::UriPath, b"Hi"),
+                (CoapOption::UriPath, b"Test"),
+            ],
+            &mut options,
+        )
+        .unwrap();
         let packet = Packet::new(
-            MessageType::NonConfirmable,
+            MessageType::Confirmable,
             MessageClass::Request(RequestType::Get),
             /* version= */ 1,
-            /* message_id= */ 5117,
-            /* token= */ &[0xD0, 0xE2, 0x4D, 0xAC],
-            /* options= */ &mut Vec::new(),
-            /* payload= */ "Hello".as_bytes(),
+            /* message_id= */ 33950,
+            /* token= */ &[0x51, 0x55, 0x77, 0xE8],
+            /* options= */ &mut options,
+            /* payload= */ &[],
+        );
+        let bytes = packet.to_bytes().unwrap();
+        assert_eq!(
+            &bytes[..],
+            &[
+                0x44, 0x01, 0x84, 0x9e, 0x51, 0x55, 0x77, 0xe8, 0xb2, 0x48, 0x69, 0x04, 0x54, 0x65,
+                0x73, 0x74, 0x43, 0x61, 0x3d, 0x31
+            ]
+        );
+        assert_eq!(packet.options, Packet::from_bytes(&bytes).unwrap().options);
+
+        let mut full = Vec::new();
+        let pairs = [(CoapOption::UriPath, &b"a"[..]); MAX_OPTIONS + 1];
+        assert_eq!(
+            Err(MessageError::OptionsLimitExceeded),
+            Packet::options_from_pairs(&pairs, &mut full)
         );
-        assert_eq!(None, packet.get_observe_value());
-        // p.set_observe_value(0);
-        // assert_eq!(Some(Ok(0)), p.get_observe_value());
     }
 
     #[test]
-    fn observe_some() {
-        let options = &[OptionPair {
-            num: CoapOption::Observe.into(),
-            value: &[10],
-        }];
+    fn request_tag_round_trip() {
+        let mut options = Vec::new();
+        options
+            .push(OptionPair {
+                num: CoapOption::RequestTag.into(),
+                value: &[0xAB, 0xCD],
+            })
+            .unwrap();
         let packet = Packet::new(
-            MessageType::NonConfirmable,
-            MessageClass::Request(RequestType::Get),
+            MessageType::Confirmable,
+            MessageClass::Request(RequestType::Put),
             /* version= */ 1,
-            /* message_id= */ 5117,
-            /* token= */ &[0xD0, 0xE2, 0x4D, 0xAC],
-            /* options= */ &mut Vec::from_slice(options).unwrap(),
-            /* payload= */ "Hello".as_bytes(),
+            /* message_id= */ 1,
+            /* token= */ &[],
+            /* options= */ &mut options,
+            /* payload= */ &[],
+        );
+        let bytes = packet.to_bytes().unwrap();
+        assert_eq!(
+            &[0x40, 0x03, 0x00, 0x01, 0xE2, 0x00, 0x17, 0xAB, 0xCD],
+            &bytes[..]
         );
-        assert_eq!(Some(10), packet.get_observe_value());
-    }
 
-    #[test]
-    fn options_limit_exceeded() {
-        let buf = [
-            0x40, 0x01, 0x00, 0x00, 0x61, 0x31, 0x01, 0x32, 0x01, 0x33, 0x01, 0x34, 0x01, 0x35,
-            0x01, 0x36, 0x01, 0x37, 0x01, 0x38, 0x01, 0x39, 0x02, 0x31, 0x30, 0x02, 0x31, 0x31,
-            0x02, 0x31, 0x32, 0x02, 0x31, 0x33, 0x02, 0x31, 0x34, 0x02, 0x31, 0x35, 0x02, 0x31,
-            0x36, 0x02, 0x31, 0x37, 0x02, 0x31, 0x38, 0x02, 0x31, 0x39, 0x02, 0x32, 0x30, 0x02,
-            0x32, 0x31, 0x02, 0x32, 0x32, 0x02, 0x32, 0x33, 0x02, 0x32, 0x34, 0x02, 0x32, 0x35,
-            0x02, 0x32, 0x36, 0x02, 0x32, 0x37, 0x02, 0x32, 0x38, 0x02, 0x32, 0x39, 0x02, 0x33,
-            0x30, 0x02, 0x33, 0x31, 0x02, 0x33, 0x32, 0x02, 0x33, 0x33,
-        ];
-        let p = Packet::from_bytes(&buf);
-        assert_eq!(MessageError::OptionsLimitExceeded, p.unwrap_err());
+        let decoded = Packet::from_bytes(&bytes).unwrap();
+        assert_eq!(292, decoded.options[0].num);
+        assert_eq!(
+            &[0xAB, 0xCD],
+            decoded
+                .get_first_option(CoapOption::RequestTag)
+                .unwrap()
+                .value
+        );
     }
 }
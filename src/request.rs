@@ -1,10 +1,29 @@
 use crate::{
     error::{InvalidObserve, MessageError},
-    packet::{CoapOption, MessageClass, ObserveOption, Packet, RequestType},
+    packet::{
+        CoapOption, ContentFormat, MessageClass, MessageType, ObserveOption, Packet, RequestType,
+        ResponseType,
+    },
     PATH_MAX_SIZE,
 };
 use core::convert::TryFrom;
-use heapless::String;
+use heapless::{String, Vec};
+
+/// The request method, with GET requests that register or deregister an
+/// observation told apart from plain GETs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RequestKind {
+    Get,
+    ObserveRegister,
+    ObserveDeregister,
+    Post,
+    Put,
+    Delete,
+    Fetch,
+    Patch,
+    IPatch,
+    UnKnown,
+}
 
 /// The CoAP request.
 #[derive(Clone, Debug, PartialEq)]
@@ -21,6 +40,12 @@ impl<'a, Endpoint> CoapRequest<'a, Endpoint> {
         }
     }
 
+    /// Takes the packet by value. `CoapRequest` only borrows its packet, so
+    /// the request is returned inside an `OwnedCoapRequest` that stores it.
+    pub fn from_owned(packet: Packet<'a>, source: Endpoint) -> OwnedCoapRequest<'a, Endpoint> {
+        OwnedCoapRequest::new(packet, source)
+    }
+
     pub fn get_method(&self) -> &RequestType {
         match self.message.get_code() {
             MessageClass::Request(RequestType::Get) => &RequestType::Get,
@@ -34,37 +59,226 @@ impl<'a, Endpoint> CoapRequest<'a, Endpoint> {
         }
     }
 
+    /// Returns the Uri-Path segments joined into an absolute path, each
+    /// segment preceded by '/'. A request without Uri-Path options returns
+    /// an empty string.
     pub fn get_path(&self) -> Result<String<PATH_MAX_SIZE>, MessageError> {
-        let mut s = String::<PATH_MAX_SIZE>::new();
+        self.joined_path()
+    }
+
+    /// Returns the method and the path, as a key for looking up the handler
+    /// of the request.
+    pub fn route_key<const N: usize>(&self) -> Result<(RequestType, String<N>), MessageError> {
+        Ok((*self.get_method(), self.joined_path()?))
+    }
+
+    fn joined_path<const N: usize>(&self) -> Result<String<N>, MessageError> {
+        let mut s = String::<N>::new();
+        for option_pair in self.message.get_options(CoapOption::UriPath) {
+            let segment =
+                core::str::from_utf8(option_pair.value).map_err(|_| MessageError::InvalidUtf8)?;
+            if s.push('/').is_err() || s.push_str(segment).is_err() {
+                return Err(MessageError::PathLengthExceeded);
+            }
+        }
+        Ok(s)
+    }
+
+    /// Same as `get_path`, but percent-decodes the segments. Returns
+    /// InvalidPercentEncoding for a `%` not followed by two hex digits, and
+    /// for an encoded `/`, which would be mistaken for a segment separator.
+    pub fn get_path_decoded<const N: usize>(&self) -> Result<String<N>, MessageError> {
+        let mut path = Vec::<u8, N>::new();
         for option_pair in self.message.get_options(CoapOption::UriPath) {
-            match s.push_str(core::str::from_utf8(option_pair.value).unwrap()) {
-                Err(_) => {
-                    return Err(MessageError::PathLengthExceeded);
-                }
-                _ => {}
+            path.push(b'/')
+                .map_err(|_| MessageError::PathLengthExceeded)?;
+            let mut bytes = option_pair.value.iter();
+            while let Some(&b) = bytes.next() {
+                let b = if b == b'%' {
+                    let mut hex_digit = || {
+                        bytes
+                            .next()
+                            .and_then(|&d| (d as char).to_digit(16))
+                            .ok_or(MessageError::InvalidPercentEncoding)
+                    };
+                    match (hex_digit()? << 4 | hex_digit()?) as u8 {
+                        b'/' => return Err(MessageError::InvalidPercentEncoding),
+                        decoded => decoded,
+                    }
+                } else {
+                    b
+                };
+                path.push(b).map_err(|_| MessageError::PathLengthExceeded)?;
             }
         }
-        return Ok(s);
+        String::from_utf8(path).map_err(|_| MessageError::InvalidUtf8)
     }
 
     /// Returns the flag in the Observe option or InvalidObserve if the flag
-    /// was provided but not understood.
+    /// was provided but not understood. Returns None if the option is absent.
     pub fn get_observe_flag(&self) -> Option<Result<ObserveOption, InvalidObserve>> {
+        match self.message.get_observe_value_checked()? {
+            Ok(value) => Some(ObserveOption::try_from(value as usize)),
+            Err(_) => Some(Err(InvalidObserve)),
+        }
+    }
+
+    /// Returns whether the request registers or deregisters an observation.
+    /// Absent or unrecognized Observe values yield None, so the request
+    /// should be handled as a plain GET (RFC 7641, section 2).
+    pub fn observe_intent(&self) -> Option<ObserveOption> {
+        self.message
+            .get_observe_value_checked()
+            .and_then(|value| value.ok())
+            .and_then(|value| ObserveOption::try_from(value as usize).ok())
+    }
+
+    /// Returns the request method, resolving the Observe option of GET
+    /// requests.
+    pub fn request_kind(&self) -> RequestKind {
+        match self.get_method() {
+            RequestType::Get => match self.observe_intent() {
+                Some(ObserveOption::Register) => RequestKind::ObserveRegister,
+                Some(ObserveOption::Deregister) => RequestKind::ObserveDeregister,
+                None => RequestKind::Get,
+            },
+            RequestType::Post => RequestKind::Post,
+            RequestType::Put => RequestKind::Put,
+            RequestType::Delete => RequestKind::Delete,
+            RequestType::Fetch => RequestKind::Fetch,
+            RequestType::Patch => RequestKind::Patch,
+            RequestType::IPatch => RequestKind::IPatch,
+            RequestType::UnKnown => RequestKind::UnKnown,
+        }
+    }
+
+    /// Returns the total body size the client declared in the Size1
+    /// option, if present and well-formed.
+    pub fn declared_size1(&self) -> Option<u32> {
         self.message
-            .get_observe_value()
-            .map(|value| usize::try_from(value).unwrap())
-            .map_or(Some(Err(InvalidObserve)), |value| {
-                Some(ObserveOption::try_from(value))
-            })
+            .option_u32(CoapOption::Size1)
+            .and_then(|value| value.ok())
+    }
+
+    /// Picks the response format: the one in the Accept option if it is
+    /// available, or the first available one if there is no Accept option.
+    /// Returns None if the accepted format isn't available, in which case
+    /// the server should respond with 4.06 Not Acceptable.
+    pub fn select_format(&self, available: &[ContentFormat]) -> Option<ContentFormat> {
+        match self.message.option_u16(CoapOption::Accept) {
+            None => available.first().copied(),
+            Some(Ok(accept)) => available
+                .iter()
+                .copied()
+                .find(|format| usize::from(*format) == accept as usize),
+            Some(Err(_)) => None,
+        }
+    }
+
+    /// Returns the 4.06 Not Acceptable response for a failed format
+    /// negotiation. The response borrows the request token it echoes, so it
+    /// lives as long as the request packet. A Confirmable request is
+    /// answered with a piggybacked ACK reusing the request message ID, other
+    /// requests with a Non-confirmable response, which needs a message ID of
+    /// its own: `new_message_id`.
+    pub fn not_acceptable_response(&self, new_message_id: u16) -> Packet<'a> {
+        let (message_type, message_id) = match self.message.get_type() {
+            MessageType::Confirmable => {
+                (MessageType::Acknowledgement, self.message.get_message_id())
+            }
+            _ => (MessageType::NonConfirmable, new_message_id),
+        };
+        Packet::new(
+            message_type,
+            MessageClass::Response(ResponseType::NotAcceptable),
+            self.message.get_version(),
+            message_id,
+            self.message.get_token(),
+            &mut Vec::new(),
+            &[],
+        )
+    }
+
+    /// Returns the ACK piggybacking the response to a Confirmable request,
+    /// reusing the request message ID and echoing its token.
+    pub fn piggyback<'b>(&self, code: ResponseType, payload: &'b [u8]) -> Packet<'b>
+    where
+        'a: 'b,
+    {
+        Packet::new(
+            MessageType::Acknowledgement,
+            MessageClass::Response(code),
+            self.message.get_version(),
+            self.message.get_message_id(),
+            self.message.token,
+            &mut Vec::new(),
+            payload,
+        )
+    }
+
+    /// Returns the empty ACK acknowledging the request, sent before a
+    /// separate response (RFC 7252, section 5.2.2).
+    pub fn separate_response_ack(&self) -> Packet<'static> {
+        Packet::empty_ack(self.message.get_message_id())
+    }
+
+    /// Returns the deferred Confirmable response carrying the request
+    /// token and a fresh message ID.
+    pub fn separate_response<'b>(
+        &self,
+        code: ResponseType,
+        payload: &'b [u8],
+        new_message_id: u16,
+    ) -> Packet<'b>
+    where
+        'a: 'b,
+    {
+        Packet::new(
+            MessageType::Confirmable,
+            MessageClass::Response(code),
+            self.message.get_version(),
+            new_message_id,
+            self.message.token,
+            &mut Vec::new(),
+            payload,
+        )
+    }
+}
+
+/// A request owning its packet, for callers that don't keep the decoded
+/// packet alive separately.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OwnedCoapRequest<'a, Endpoint> {
+    pub message: Packet<'a>,
+    pub source: Option<Endpoint>,
+}
+
+impl<'a, Endpoint> OwnedCoapRequest<'a, Endpoint> {
+    pub fn new(packet: Packet<'a>, source: Endpoint) -> Self {
+        OwnedCoapRequest {
+            message: packet,
+            source: Some(source),
+        }
+    }
+
+    /// Borrows the owned packet as a request with a copy of the source.
+    pub fn request(&self) -> CoapRequest<'_, Endpoint>
+    where
+        Endpoint: Clone,
+    {
+        CoapRequest {
+            message: &self.message,
+            source: self.source.clone(),
+        }
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::packet::{MessageType, OptionPair};
-    use heapless::Vec;
+    use crate::packet::OptionPair;
 
+    #[derive(Clone)]
     struct Endpoint(());
 
     #[test]
@@ -87,6 +301,145 @@ mod test {
         assert!(request.source.is_some())
     }
 
+    #[test]
+    fn test_request_from_owned() {
+        let options = &[
+            OptionPair {
+                num: CoapOption::UriPath.into(),
+                value: b"Hi",
+            },
+            OptionPair {
+                num: CoapOption::UriPath.into(),
+                value: b"Test",
+            },
+        ];
+        let packet = Packet::new(
+            MessageType::Confirmable,
+            MessageClass::Request(RequestType::Post),
+            /* version= */ 1,
+            /* message_id= */ 42,
+            /* token= */ &[0x17, 0x38],
+            /* options= */ &mut Vec::from_slice(options).unwrap(),
+            /* payload= */ &[],
+        );
+        let owned = CoapRequest::from_owned(packet, Endpoint(()));
+        let request = owned.request();
+
+        assert!(request.source.is_some());
+        assert_eq!(&RequestType::Post, request.get_method());
+        assert_eq!("/Hi/Test", request.get_path().unwrap());
+    }
+
+    #[test]
+    fn piggyback() {
+        let packet = get_request(&[]);
+        let request = CoapRequest::from_packet(&packet, Endpoint(()));
+
+        let response = request.piggyback(ResponseType::Content, b"22.5");
+        assert_eq!(MessageType::Acknowledgement, response.get_type());
+        assert_eq!(
+            MessageClass::Response(ResponseType::Content),
+            response.get_code()
+        );
+        assert_eq!(42, response.get_message_id());
+        assert_eq!(&[0x17, 0x38], response.get_token());
+        assert_eq!(b"22.5", response.get_payload());
+    }
+
+    #[test]
+    fn separate_response() {
+        let packet = get_request(&[]);
+        let request = CoapRequest::from_packet(&packet, Endpoint(()));
+
+        let ack = request.separate_response_ack();
+        assert_eq!(MessageType::Acknowledgement, ack.get_type());
+        assert_eq!(MessageClass::Empty, ack.get_code());
+        assert_eq!(42, ack.get_message_id());
+        assert!(!ack.has_token());
+
+        let response = request.separate_response(ResponseType::Content, b"22.5", 43);
+        assert_eq!(MessageType::Confirmable, response.get_type());
+        assert_eq!(
+            MessageClass::Response(ResponseType::Content),
+            response.get_code()
+        );
+        assert_eq!(43, response.get_message_id());
+        assert_eq!(&[0x17, 0x38], response.get_token());
+        assert_eq!(b"22.5", response.get_payload());
+    }
+
+    #[test]
+    fn path_invalid_utf8() {
+        let options = &[OptionPair {
+            num: CoapOption::UriPath.into(),
+            value: &[0xFF, 0xFE],
+        }];
+        let packet = Packet::new(
+            MessageType::Confirmable,
+            MessageClass::Request(RequestType::Get),
+            /* version= */ 1,
+            /* message_id= */ 42,
+            /* token= */ &[],
+            /* options= */ &mut Vec::from_slice(options).unwrap(),
+            /* payload= */ &[],
+        );
+        let request = CoapRequest::from_packet(&packet, Endpoint(()));
+        assert_eq!(MessageError::InvalidUtf8, request.get_path().unwrap_err());
+    }
+
+    #[test]
+    fn path_decoded() {
+        let packet = get_request(&[OptionPair {
+            num: CoapOption::UriPath.into(),
+            value: b"a%20b",
+        }]);
+        let request = CoapRequest::from_packet(&packet, Endpoint(()));
+        assert_eq!("/a b", request.get_path_decoded::<16>().unwrap());
+        assert_eq!("/a%20b", request.get_path().unwrap());
+
+        let packet = get_request(&[OptionPair {
+            num: CoapOption::UriPath.into(),
+            value: b"a%2",
+        }]);
+        let request = CoapRequest::from_packet(&packet, Endpoint(()));
+        assert_eq!(
+            MessageError::InvalidPercentEncoding,
+            request.get_path_decoded::<16>().unwrap_err()
+        );
+
+        let packet = get_request(&[OptionPair {
+            num: CoapOption::UriPath.into(),
+            value: b"a%2Fb",
+        }]);
+        let request = CoapRequest::from_packet(&packet, Endpoint(()));
+        assert_eq!(
+            MessageError::InvalidPercentEncoding,
+            request.get_path_decoded::<16>().unwrap_err()
+        );
+    }
+
+    #[test]
+    fn route_key() {
+        let packet = get_request(&[
+            OptionPair {
+                num: CoapOption::UriPath.into(),
+                value: b"a",
+            },
+            OptionPair {
+                num: CoapOption::UriPath.into(),
+                value: b"b",
+            },
+        ]);
+        let request = CoapRequest::from_packet(&packet, Endpoint(()));
+        let (method, path) = request.route_key::<16>().unwrap();
+        assert_eq!(RequestType::Get, method);
+        assert_eq!("/a/b", path);
+        assert_eq!(
+            MessageError::PathLengthExceeded,
+            request.route_key::<3>().unwrap_err()
+        );
+    }
+
     #[test]
     fn path_length_exceeded() {
         let buf = [
@@ -108,4 +461,148 @@ mod test {
             request.get_path().unwrap_err()
         );
     }
+
+    fn get_request<'a>(options: &[OptionPair<'a>]) -> Packet<'a> {
+        Packet::new(
+            MessageType::Confirmable,
+            MessageClass::Request(RequestType::Get),
+            /* version= */ 1,
+            /* message_id= */ 42,
+            /* token= */ &[0x17, 0x38],
+            /* options= */ &mut Vec::from_slice(options).unwrap(),
+            /* payload= */ &[],
+        )
+    }
+
+    #[test]
+    fn observe_intent_register() {
+        let packet = get_request(&[OptionPair {
+            num: CoapOption::Observe.into(),
+            value: &[],
+        }]);
+        let request = CoapRequest::from_packet(&packet, Endpoint(()));
+        assert_eq!(Some(ObserveOption::Register), request.observe_intent());
+    }
+
+    #[test]
+    fn observe_intent_deregister() {
+        let packet = get_request(&[OptionPair {
+            num: CoapOption::Observe.into(),
+            value: &[1],
+        }]);
+        let request = CoapRequest::from_packet(&packet, Endpoint(()));
+        assert_eq!(Some(ObserveOption::Deregister), request.observe_intent());
+    }
+
+    #[test]
+    fn observe_intent_absent() {
+        let packet = get_request(&[]);
+        let request = CoapRequest::from_packet(&packet, Endpoint(()));
+        assert_eq!(None, request.get_observe_flag());
+        assert_eq!(None, request.observe_intent());
+    }
+
+    #[test]
+    fn observe_intent_unrecognized() {
+        let packet = get_request(&[OptionPair {
+            num: CoapOption::Observe.into(),
+            value: &[2],
+        }]);
+        let request = CoapRequest::from_packet(&packet, Endpoint(()));
+        assert_eq!(None, request.observe_intent());
+    }
+
+    #[test]
+    fn observe_flag_too_long() {
+        let packet = get_request(&[OptionPair {
+            num: CoapOption::Observe.into(),
+            value: &[0, 0, 0, 1],
+        }]);
+        let request = CoapRequest::from_packet(&packet, Endpoint(()));
+        assert_eq!(Some(Err(InvalidObserve)), request.get_observe_flag());
+        assert_eq!(None, request.observe_intent());
+    }
+
+    #[test]
+    fn request_kind() {
+        let packet = get_request(&[]);
+        let request = CoapRequest::from_packet(&packet, Endpoint(()));
+        assert_eq!(RequestKind::Get, request.request_kind());
+
+        let packet = get_request(&[OptionPair {
+            num: CoapOption::Observe.into(),
+            value: &[],
+        }]);
+        let request = CoapRequest::from_packet(&packet, Endpoint(()));
+        assert_eq!(RequestKind::ObserveRegister, request.request_kind());
+
+        let packet = get_request(&[OptionPair {
+            num: CoapOption::Observe.into(),
+            value: &[1],
+        }]);
+        let request = CoapRequest::from_packet(&packet, Endpoint(()));
+        assert_eq!(RequestKind::ObserveDeregister, request.request_kind());
+    }
+
+    #[test]
+    fn select_format() {
+        let available = [
+            ContentFormat::ApplicationJSON,
+            ContentFormat::ApplicationCBOR,
+        ];
+
+        let packet = get_request(&[OptionPair {
+            num: CoapOption::Accept.into(),
+            value: &[60],
+        }]);
+        let request = CoapRequest::from_packet(&packet, Endpoint(()));
+        assert_eq!(
+            Some(ContentFormat::ApplicationCBOR),
+            request.select_format(&available)
+        );
+
+        let packet = get_request(&[]);
+        let request = CoapRequest::from_packet(&packet, Endpoint(()));
+        assert_eq!(
+            Some(ContentFormat::ApplicationJSON),
+            request.select_format(&available)
+        );
+        assert_eq!(None, request.select_format(&[]));
+
+        let packet = get_request(&[OptionPair {
+            num: CoapOption::Accept.into(),
+            value: &[],
+        }]);
+        let request = CoapRequest::from_packet(&packet, Endpoint(()));
+        assert_eq!(None, request.select_format(&available));
+    }
+
+    #[test]
+    fn not_acceptable_response() {
+        let packet = get_request(&[]);
+        let request = CoapRequest::from_packet(&packet, Endpoint(()));
+        let response = request.not_acceptable_response(7);
+        assert_eq!(MessageType::Acknowledgement, response.get_type());
+        assert_eq!(
+            MessageClass::Response(ResponseType::NotAcceptable),
+            response.get_code()
+        );
+        assert_eq!(0x86, response.code_byte());
+        assert_eq!(42, response.get_message_id());
+        assert_eq!(&[0x17, 0x38], response.get_token());
+
+        let packet = packet.to_non_confirmable();
+        let request = CoapRequest::from_packet(&packet, Endpoint(()));
+        let response = request.not_acceptable_response(7);
+        assert_eq!(MessageType::NonConfirmable, response.get_type());
+        assert_eq!(7, response.get_message_id());
+        assert_eq!(&[0x17, 0x38], response.get_token());
+    }
+
+    #[test]
+    fn empty_path() {
+        let packet = get_request(&[]);
+        let request = CoapRequest::from_packet(&packet, Endpoint(()));
+        assert_eq!("", request.get_path().unwrap());
+    }
 }
@@ -1,4 +1,7 @@
-use crate::packet::{MessageClass, Packet, ResponseType};
+use crate::{
+    error::MessageError,
+    packet::{CoapOption, MessageClass, Packet, ResponseType},
+};
 
 /// The CoAP response.
 #[derive(Clone, Debug, PartialEq)]
@@ -12,6 +15,21 @@ impl<'a> CoapResponse<'a> {
         CoapResponse { message: packet }
     }
 
+    /// Takes the packet by value. `CoapResponse` only borrows its packet, so
+    /// the response is returned inside an `OwnedCoapResponse` that stores it.
+    pub fn from_owned(packet: Packet<'a>) -> OwnedCoapResponse<'a> {
+        OwnedCoapResponse::new(packet)
+    }
+
+    /// Creates a new response, returning NotAResponse if the packet code
+    /// isn't a response code.
+    pub fn try_from_packet<'b>(packet: &'b Packet) -> Result<CoapResponse<'b>, MessageError> {
+        match packet.get_code() {
+            MessageClass::Response(_) => Ok(CoapResponse::new(packet)),
+            _ => Err(MessageError::NotAResponse),
+        }
+    }
+
     /// Returns the status.
     pub fn get_status(&self) -> &ResponseType {
         match self.message.get_code() {
@@ -20,6 +38,7 @@ impl<'a> CoapResponse<'a> {
             MessageClass::Response(ResponseType::Valid) => &ResponseType::Valid,
             MessageClass::Response(ResponseType::Changed) => &ResponseType::Changed,
             MessageClass::Response(ResponseType::Content) => &ResponseType::Content,
+            MessageClass::Response(ResponseType::Continue) => &ResponseType::Continue,
 
             MessageClass::Response(ResponseType::BadRequest) => &ResponseType::BadRequest,
             MessageClass::Response(ResponseType::Unauthorized) => &ResponseType::Unauthorized,
@@ -30,6 +49,7 @@ impl<'a> CoapResponse<'a> {
                 &ResponseType::MethodNotAllowed
             }
             MessageClass::Response(ResponseType::NotAcceptable) => &ResponseType::NotAcceptable,
+            MessageClass::Response(ResponseType::Conflict) => &ResponseType::Conflict,
             MessageClass::Response(ResponseType::PreconditionFailed) => {
                 &ResponseType::PreconditionFailed
             }
@@ -39,6 +59,13 @@ impl<'a> CoapResponse<'a> {
             MessageClass::Response(ResponseType::UnsupportedContentFormat) => {
                 &ResponseType::UnsupportedContentFormat
             }
+            MessageClass::Response(ResponseType::RequestEntityIncomplete) => {
+                &ResponseType::RequestEntityIncomplete
+            }
+            MessageClass::Response(ResponseType::UnprocessableEntity) => {
+                &ResponseType::UnprocessableEntity
+            }
+            MessageClass::Response(ResponseType::TooManyRequests) => &ResponseType::TooManyRequests,
 
             MessageClass::Response(ResponseType::InternalServerError) => {
                 &ResponseType::InternalServerError
@@ -52,15 +79,68 @@ impl<'a> CoapResponse<'a> {
             MessageClass::Response(ResponseType::ProxyingNotSupported) => {
                 &ResponseType::ProxyingNotSupported
             }
+            MessageClass::Response(ResponseType::HopLimitReached) => &ResponseType::HopLimitReached,
             _ => &ResponseType::UnKnown,
         }
     }
+
+    /// Returns the Max-Age of a 4.29 Too Many Requests response, which is
+    /// the number of seconds the client should wait before retrying.
+    pub fn retry_after_hint(&self) -> Option<u32> {
+        if self.get_status() != &ResponseType::TooManyRequests {
+            return None;
+        }
+        self.message
+            .option_u32(CoapOption::MaxAge)
+            .and_then(|value| value.ok())
+    }
+
+    /// Returns the ETag of the representation, which in a 2.03 Valid
+    /// response is the ETag the server confirmed.
+    pub fn get_etag(&self) -> Option<&[u8]> {
+        self.message
+            .get_first_option(CoapOption::ETag)
+            .map(|option| option.value)
+    }
+
+    /// Returns whether this is a 2.03 Valid response confirming a cached
+    /// representation by its ETag (RFC 7252, section 5.9.1.3).
+    pub fn is_valid_revalidation(&self) -> bool {
+        self.get_status() == &ResponseType::Valid && self.get_etag().is_some()
+    }
+
+    /// Returns the number of the next block to request, or None if the
+    /// response has no valid Block2 option or is the last block.
+    pub fn next_block2_request_num(&self) -> Option<u32> {
+        match self.message.get_block2()? {
+            Ok(block) if block.more => Some(block.num as u32 + 1),
+            _ => None,
+        }
+    }
+}
+
+/// A response owning its packet, for callers that don't keep the decoded
+/// packet alive separately.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OwnedCoapResponse<'a> {
+    pub message: Packet<'a>,
+}
+
+impl<'a> OwnedCoapResponse<'a> {
+    pub fn new(packet: Packet<'a>) -> Self {
+        OwnedCoapResponse { message: packet }
+    }
+
+    /// Borrows the owned packet as a response.
+    pub fn response(&self) -> CoapResponse<'_> {
+        CoapResponse::new(&self.message)
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::packet::MessageType;
+    use crate::packet::{BlockValue, MessageType, OptionPair, RequestType};
     use heapless::Vec;
 
     #[test]
@@ -77,4 +157,125 @@ mod test {
         let opt_resp = CoapResponse::new(&packet);
         assert_eq!(opt_resp.get_status(), &ResponseType::Content);
     }
+
+    #[test]
+    fn test_response_from_owned() {
+        let packet = Packet::new(
+            MessageType::Acknowledgement,
+            MessageClass::Response(ResponseType::Changed),
+            /* version= */ 1,
+            /* message_id= */ 42,
+            /* token= */ &[0x17, 0x38],
+            /* options= */ &mut Vec::new(),
+            /* payload= */ &[],
+        );
+        let owned = CoapResponse::from_owned(packet);
+        assert_eq!(owned.response().get_status(), &ResponseType::Changed);
+    }
+
+    #[test]
+    fn test_try_from_packet() {
+        let packet = Packet::new(
+            MessageType::Acknowledgement,
+            MessageClass::Response(ResponseType::Content),
+            /* version= */ 1,
+            /* message_id= */ 42,
+            /* token= */ &[0x17, 0x38],
+            /* options= */ &mut Vec::new(),
+            /* payload= */ "Hello".as_bytes(),
+        );
+        let resp = CoapResponse::try_from_packet(&packet).unwrap();
+        assert_eq!(resp.get_status(), &ResponseType::Content);
+    }
+
+    #[test]
+    fn test_try_from_packet_not_a_response() {
+        let packet = Packet::new(
+            MessageType::Confirmable,
+            MessageClass::Request(RequestType::Get),
+            /* version= */ 1,
+            /* message_id= */ 42,
+            /* token= */ &[0x17, 0x38],
+            /* options= */ &mut Vec::new(),
+            /* payload= */ &[],
+        );
+        assert_eq!(
+            MessageError::NotAResponse,
+            CoapResponse::try_from_packet(&packet).unwrap_err()
+        );
+    }
+
+    #[test]
+    fn test_retry_after_hint() {
+        // 4.29 Too Many Requests with Max-Age of 30.
+        let buf = [0x60, 0x9d, 0x00, 0x01, 0xd1, 0x01, 0x1e];
+        let packet = Packet::from_bytes(&buf).unwrap();
+        let resp = CoapResponse::new(&packet);
+        assert_eq!(resp.get_status(), &ResponseType::TooManyRequests);
+        assert_eq!(Some(30), resp.retry_after_hint());
+
+        let options = &[OptionPair {
+            num: CoapOption::MaxAge.into(),
+            value: &[30],
+        }];
+        let packet = Packet::new(
+            MessageType::Acknowledgement,
+            MessageClass::Response(ResponseType::UnprocessableEntity),
+            /* version= */ 1,
+            /* message_id= */ 1,
+            /* token= */ &[],
+            /* options= */ &mut Vec::from_slice(options).unwrap(),
+            /* payload= */ &[],
+        );
+        let resp = CoapResponse::new(&packet);
+        assert_eq!(resp.get_status(), &ResponseType::UnprocessableEntity);
+        assert_eq!(None, resp.retry_after_hint());
+    }
+
+    #[test]
+    fn test_valid_revalidation() {
+        // 2.03 Valid with ETag 0x1234.
+        let buf = [0x60, 0x43, 0x00, 0x01, 0x42, 0x12, 0x34];
+        let packet = Packet::from_bytes(&buf).unwrap();
+        let resp = CoapResponse::new(&packet);
+        assert_eq!(resp.get_status(), &ResponseType::Valid);
+        assert_eq!(Some(&[0x12, 0x34][..]), resp.get_etag());
+        assert!(resp.is_valid_revalidation());
+
+        // 2.05 Content with the same ETag.
+        let buf = [0x60, 0x45, 0x00, 0x01, 0x42, 0x12, 0x34];
+        let packet = Packet::from_bytes(&buf).unwrap();
+        let resp = CoapResponse::new(&packet);
+        assert_eq!(Some(&[0x12, 0x34][..]), resp.get_etag());
+        assert!(!resp.is_valid_revalidation());
+    }
+
+    #[test]
+    fn test_next_block2_request_num() {
+        let mut first = [0; 3];
+        let mut last = [0; 3];
+        let mut packet = Packet::new(
+            MessageType::Acknowledgement,
+            MessageClass::Response(ResponseType::Content),
+            /* version= */ 1,
+            /* message_id= */ 42,
+            /* token= */ &[0x17, 0x38],
+            /* options= */ &mut Vec::new(),
+            /* payload= */ &[0x41; 64],
+        );
+        assert_eq!(None, CoapResponse::new(&packet).next_block2_request_num());
+
+        packet
+            .set_block2(BlockValue::new(0, true, 64).unwrap(), &mut first)
+            .unwrap();
+        assert_eq!(
+            Some(1),
+            CoapResponse::new(&packet).next_block2_request_num()
+        );
+
+        packet
+            .set_block2(BlockValue::new(5, false, 64).unwrap(), &mut last)
+            .unwrap();
+        assert_eq!(None, CoapResponse::new(&packet).next_block2_request_num());
+    }
 }
@@ -0,0 +1,211 @@
+use crate::{
+    error::MessageError,
+    packet::{MessageType, Packet},
+    PACKET_MAX_SIZE,
+};
+use heapless::Vec;
+
+/// Streaming decoder for CoAP over TCP (RFC 8323, section 3.2).
+///
+/// Bytes read from the stream are pushed into the internal buffer and
+/// complete frames are returned by `next` once all of their bytes arrived.
+/// The TCP framing has no version, type or message ID, so the decoded
+/// packets are reported as version 1 Confirmable messages with ID 0.
+#[derive(Debug, Default)]
+pub struct TcpDecoder {
+    buf: Vec<u8, PACKET_MAX_SIZE>,
+    /// Length of the frame returned by the last `next` call.
+    consumed: usize,
+}
+
+impl TcpDecoder {
+    pub fn new() -> Self {
+        TcpDecoder {
+            buf: Vec::new(),
+            consumed: 0,
+        }
+    }
+
+    /// Appends bytes read from the stream. Returns InvalidPacketLength if
+    /// the buffered bytes don't fit PACKET_MAX_SIZE.
+    pub fn push(&mut self, bytes: &[u8]) -> Result<(), MessageError> {
+        self.compact();
+        self.buf
+            .extend_from_slice(bytes)
+            .map_err(|_| MessageError::InvalidPacketLength)
+    }
+
+    /// Returns the next complete frame, or None if more bytes are needed.
+    /// A malformed frame discards the buffered bytes, since the stream
+    /// can't be resynchronized.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Option<Result<Packet<'_>, MessageError>> {
+        self.compact();
+        let header = match FrameHeader::try_from_bytes(&self.buf)? {
+            Ok(header) => header,
+            Err(err) => {
+                self.buf.clear();
+                return Some(Err(err));
+            }
+        };
+        if header.frame_length > PACKET_MAX_SIZE {
+            self.buf.clear();
+            return Some(Err(MessageError::InvalidPacketLength));
+        }
+        if header.frame_length > self.buf.len() {
+            return None;
+        }
+        self.consumed = header.frame_length;
+        Some(header.decode(&self.buf[..header.frame_length]))
+    }
+
+    /// Drops the frame returned by the last `next` call.
+    fn compact(&mut self) {
+        if self.consumed > 0 {
+            let len = self.buf.len();
+            self.buf.copy_within(self.consumed..len, 0);
+            self.buf.truncate(len - self.consumed);
+            self.consumed = 0;
+        }
+    }
+}
+
+struct FrameHeader {
+    /// Offset of the code byte.
+    code_index: usize,
+    token_length: usize,
+    frame_length: usize,
+}
+
+impl FrameHeader {
+    /// Parses the frame header, returning None if more bytes are needed.
+    fn try_from_bytes(buf: &[u8]) -> Option<Result<FrameHeader, MessageError>> {
+        let first = *buf.first()?;
+        let len = (first >> 4) as usize;
+        let token_length = (first & 0x0F) as usize;
+        if token_length > 8 {
+            return Some(Err(MessageError::InvalidTokenLength));
+        }
+
+        let (extended_bytes, base) = match len {
+            13 => (1, 13),
+            14 => (2, 269),
+            15 => (4, 65805),
+            _ => (0, len),
+        };
+        let code_index = 1 + extended_bytes;
+        if buf.len() < code_index {
+            return None;
+        }
+        let extended = buf[1..code_index]
+            .iter()
+            .fold(0usize, |acc, &b| (acc << 8) | b as usize);
+
+        let frame_length = base
+            .checked_add(extended)
+            .and_then(|length| length.checked_add(code_index + 1 + token_length));
+        Some(match frame_length {
+            Some(frame_length) => Ok(FrameHeader {
+                code_index,
+                token_length,
+                frame_length,
+            }),
+            None => Err(MessageError::InvalidPacketLength),
+        })
+    }
+
+    fn decode<'b>(&self, frame: &'b [u8]) -> Result<Packet<'b>, MessageError> {
+        let token_start = self.code_index + 1;
+        let options_start = token_start + self.token_length;
+        let mut options = Vec::new();
+        let payload = Packet::decode_options(frame, options_start, &mut options)?;
+        Ok(Packet::new(
+            MessageType::Confirmable,
+            frame[self.code_index].into(),
+            /* version= */ 1,
+            /* message_id= */ 0,
+            &frame[token_start..options_start],
+            &mut options,
+            payload,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::packet::{CoapOption, MessageClass, RequestType, ResponseType};
+
+    // GET /Hi with token 0x0102.
+    const GET_FRAME: [u8; 7] = [0x32, 0x01, 0x01, 0x02, 0xb2, 0x48, 0x69];
+
+    #[test]
+    fn decode_one_shot() {
+        let mut decoder = TcpDecoder::new();
+        decoder.push(&GET_FRAME).unwrap();
+        {
+            let packet = decoder.next().unwrap().unwrap();
+            assert_eq!(packet.get_code(), MessageClass::Request(RequestType::Get));
+            assert_eq!(packet.get_token(), &[0x01, 0x02]);
+            assert_eq!(
+                packet.get_first_option(CoapOption::UriPath).unwrap().value,
+                b"Hi"
+            );
+        }
+        assert!(decoder.next().is_none());
+    }
+
+    #[test]
+    fn decode_byte_by_byte() {
+        let mut decoder = TcpDecoder::new();
+        for (i, b) in GET_FRAME.iter().enumerate() {
+            decoder.push(&[*b]).unwrap();
+            if i + 1 < GET_FRAME.len() {
+                assert!(decoder.next().is_none());
+            }
+        }
+        {
+            let packet = decoder.next().unwrap().unwrap();
+            assert_eq!(packet.get_token(), &[0x01, 0x02]);
+        }
+        assert!(decoder.next().is_none());
+    }
+
+    #[test]
+    fn decode_multiple_frames() {
+        let mut decoder = TcpDecoder::new();
+        decoder.push(&GET_FRAME).unwrap();
+        decoder.push(&GET_FRAME[..3]).unwrap();
+        assert!(decoder.next().unwrap().is_ok());
+        assert!(decoder.next().is_none());
+        decoder.push(&GET_FRAME[3..]).unwrap();
+        assert!(decoder.next().unwrap().is_ok());
+        assert!(decoder.next().is_none());
+    }
+
+    #[test]
+    fn decode_extended_length() {
+        // 2.05 Content with a 19 byte payload, the length takes one extra byte.
+        let mut frame = [0x41; 23];
+        frame[..5].copy_from_slice(&[0xD0, 0x07, 0x45, 0xFF, 0x48]);
+        let mut decoder = TcpDecoder::new();
+        decoder.push(&frame).unwrap();
+        let packet = decoder.next().unwrap().unwrap();
+        assert_eq!(
+            packet.get_code(),
+            MessageClass::Response(ResponseType::Content)
+        );
+        assert_eq!(packet.get_payload().len(), 19);
+    }
+
+    #[test]
+    fn decode_invalid_token_length() {
+        let mut decoder = TcpDecoder::new();
+        decoder.push(&[0x09, 0x01]).unwrap();
+        assert_eq!(
+            MessageError::InvalidTokenLength,
+            decoder.next().unwrap().unwrap_err()
+        );
+        assert!(decoder.next().is_none());
+    }
+}